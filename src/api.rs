@@ -1,19 +1,30 @@
-use crate::data::FileSetData;
+use crate::data::{
+    Acknowledgement, EventNote, FileSetData, MonitorData, NewAcknowledgement, NewNote,
+};
+use crate::delivery::Deliveries;
+use crate::escalation::Escalations;
+use crate::files_seen::{FilesLastSeen, FilesQuery};
 use crate::fileset::FileSetId;
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::metrics;
+use crate::metrics::Queues;
+use crate::notifier::NotifierMessage;
+use crate::query;
+use crate::query::{CountsQuery, Format};
+use crate::silence::{NewSilence, SilenceTargets, Silences};
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use chrono::Utc;
 use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock as RwLock_Tokio;
 
-
 /// HTTP GET a list of all the filesets
 #[get("/fileset")]
 pub(crate) async fn get_filesets(
     filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
 ) -> impl Responder {
     let fileset_data = filesets_data_rwlock.read().await;
-    HttpResponse::Ok().json(&fileset_data.keys().cloned().collect::<Vec<FileSetId>>())
+    HttpResponse::Ok().json(fileset_data.keys().cloned().collect::<Vec<FileSetId>>())
 }
 
 /// HTTP GET a list of all the monitors for a given fileset
@@ -25,7 +36,7 @@ pub(crate) async fn get_monitors_for_fileset(
     let fileset_data = filesets_data_rwlock.read().await;
     if let Some(fileset) = fileset_data.get::<String>(&fileset_id) {
         HttpResponse::Ok().json(
-            &fileset
+            fileset
                 .monitor_data
                 .keys()
                 .cloned()
@@ -45,7 +56,7 @@ pub(crate) async fn get_monitor(
     let fileset_data = filesets_data_rwlock.read().await;
     if let Some(fileset) = fileset_data.get::<String>(&path.0) {
         if let Some(monitor_data) = fileset.monitor_data.get(&path.1) {
-            HttpResponse::Ok().json(&monitor_data)
+            HttpResponse::Ok().json(monitor_data)
         } else {
             HttpResponse::NotFound().json(json!({ "error": "monitor not found" }))
         }
@@ -54,6 +65,156 @@ pub(crate) async fn get_monitor(
     }
 }
 
+/// HTTP GET a single recent event for a specific monitor for a specific fileset
+#[get("/fileset/{fileset_id}/monitor/{monitor_id}/event/{event_id}")]
+pub(crate) async fn get_event(
+    filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
+    path: web::Path<(String, String, String)>,
+) -> impl Responder {
+    let fileset_data = filesets_data_rwlock.read().await;
+    let monitor_data = match find_monitor_data(&fileset_data, &path.0, &path.1) {
+        Ok(monitor_data) => monitor_data,
+        Err(response) => return response,
+    };
+    match monitor_data.event(&path.2) {
+        Some(ev) => HttpResponse::Ok().json(ev),
+        None => HttpResponse::NotFound().json(json!({ "error": "event not found" })),
+    }
+}
+
+/// HTTP POST to acknowledge a recent event. Also acknowledges the monitor's alert if it's
+/// escalating.
+#[post("/fileset/{fileset_id}/monitor/{monitor_id}/event/{event_id}/ack")]
+pub(crate) async fn acknowledge_event(
+    filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
+    escalations_rwlock: web::Data<Arc<RwLock_Tokio<Escalations>>>,
+    queues: web::Data<Queues>,
+    path: web::Path<(String, String, String)>,
+    new_acknowledgement: web::Json<NewAcknowledgement>,
+) -> impl Responder {
+    if new_acknowledgement.by.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "by is required" }));
+    }
+    let fileset_data = filesets_data_rwlock.read().await;
+    let monitor_data = match find_monitor_data(&fileset_data, &path.0, &path.1) {
+        Ok(monitor_data) => monitor_data,
+        Err(response) => return response,
+    };
+    // Check and acknowledge while holding the event's lock, so concurrent requests can't
+    // both succeed
+    let mut already_acknowledged = false;
+    let ev = match monitor_data.update_event(&path.2, |ev| {
+        if ev.acknowledgement.is_some() {
+            already_acknowledged = true;
+            return;
+        }
+        ev.acknowledgement = Some(Acknowledgement {
+            by: new_acknowledgement.by.clone(),
+            at: Utc::now(),
+        })
+    }) {
+        Some(_) if already_acknowledged => {
+            return HttpResponse::Conflict().json(json!({ "error": "event already acknowledged" }))
+        }
+        Some(ev) => ev,
+        None => return HttpResponse::NotFound().json(json!({ "error": "event not found" })),
+    };
+    drop(fileset_data);
+    println!(
+        "Event {} for {} / {} acknowledged by {}",
+        path.2, path.0, path.1, new_acknowledgement.by
+    );
+    if escalations_rwlock
+        .write()
+        .await
+        .acknowledge(&path.0, &path.1)
+        .is_some()
+    {
+        println!("Escalation for {} / {} acknowledged", path.0, path.1);
+    }
+    // Let notifiers such as PagerDuty acknowledge the alert too
+    let _ = queues
+        .notifier
+        .send(NotifierMessage::Acknowledge(
+            path.0.clone(),
+            path.1.clone(),
+            ev.captures.clone(),
+        ))
+        .await;
+    HttpResponse::Ok().json(ev)
+}
+
+/// HTTP POST a note about a recent event
+#[post("/fileset/{fileset_id}/monitor/{monitor_id}/event/{event_id}/note")]
+pub(crate) async fn add_event_note(
+    filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
+    path: web::Path<(String, String, String)>,
+    new_note: web::Json<NewNote>,
+) -> impl Responder {
+    let new_note = new_note.into_inner();
+    if new_note.by.is_empty() || new_note.text.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "by and text are required" }));
+    }
+    let fileset_data = filesets_data_rwlock.read().await;
+    let monitor_data = match find_monitor_data(&fileset_data, &path.0, &path.1) {
+        Ok(monitor_data) => monitor_data,
+        Err(response) => return response,
+    };
+    let note = EventNote {
+        by: new_note.by,
+        at: Utc::now(),
+        text: new_note.text,
+    };
+    match monitor_data.update_event(&path.2, |ev| ev.notes.push(note)) {
+        Some(ev) => HttpResponse::Created().json(ev),
+        None => HttpResponse::NotFound().json(json!({ "error": "event not found" })),
+    }
+}
+
+/// Find the data for a monitor on a fileset, or the error response if either doesn't exist
+fn find_monitor_data<'a>(
+    fileset_data: &'a HashMap<FileSetId, FileSetData>,
+    fileset_id: &str,
+    monitor_id: &str,
+) -> Result<&'a MonitorData, HttpResponse> {
+    let fileset = fileset_data
+        .get(fileset_id)
+        .ok_or_else(|| HttpResponse::NotFound().json(json!({ "error": "fileset not found" })))?;
+    fileset
+        .monitor_data
+        .get(monitor_id)
+        .ok_or_else(|| HttpResponse::NotFound().json(json!({ "error": "monitor not found" })))
+}
+
+/// HTTP GET zero-filled series of event counts over a time range, as JSON or CSV
+#[get("/counts")]
+pub(crate) async fn get_counts(
+    filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
+    query: web::Query<CountsQuery>,
+) -> impl Responder {
+    let fileset_data = filesets_data_rwlock.read().await;
+    if let Some(fileset_id) = &query.fileset {
+        if !fileset_data.contains_key(fileset_id) {
+            return HttpResponse::NotFound().json(json!({ "error": "fileset not found" }));
+        }
+    }
+    if let Some(monitor_id) = &query.monitor {
+        if !fileset_data
+            .values()
+            .any(|fileset| fileset.monitor_data.contains_key(monitor_id))
+        {
+            return HttpResponse::NotFound().json(json!({ "error": "monitor not found" }));
+        }
+    }
+    match query::counts(&fileset_data, &query) {
+        Ok(result) if query.format == Format::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .body(result.to_csv()),
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(error) => HttpResponse::BadRequest().json(json!({ "error": error })),
+    }
+}
+
 /// Dump the entire in-memory data set
 #[get("/dump")]
 pub(crate) async fn dump(
@@ -63,3 +224,117 @@ pub(crate) async fn dump(
     HttpResponse::Ok().body(serde_json::to_string(&*fileset_data).expect("Couldn't serialise data"))
 }
 
+/// HTTP GET a list of all unexpired silences
+#[get("/silence")]
+pub(crate) async fn get_silences(
+    silences_rwlock: web::Data<Arc<RwLock_Tokio<Silences>>>,
+) -> impl Responder {
+    let silences = silences_rwlock.read().await;
+    HttpResponse::Ok().json(silences.active())
+}
+
+/// HTTP POST a new silence
+#[post("/silence")]
+pub(crate) async fn create_silence(
+    silences_rwlock: web::Data<Arc<RwLock_Tokio<Silences>>>,
+    silence_targets: web::Data<SilenceTargets>,
+    new_silence: web::Json<NewSilence>,
+) -> impl Responder {
+    let mut silences = silences_rwlock.write().await;
+    match silences.add(new_silence.into_inner(), &silence_targets) {
+        Ok(silence) => HttpResponse::Created().json(silence),
+        Err(error) => HttpResponse::BadRequest().json(json!({ "error": error })),
+    }
+}
+
+/// HTTP DELETE a silence
+#[delete("/silence/{silence_id}")]
+pub(crate) async fn delete_silence(
+    silences_rwlock: web::Data<Arc<RwLock_Tokio<Silences>>>,
+    silence_id: web::Path<String>,
+) -> impl Responder {
+    let mut silences = silences_rwlock.write().await;
+    if let Some(silence) = silences.remove(&silence_id) {
+        HttpResponse::Ok().json(silence)
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "silence not found" }))
+    }
+}
+
+/// HTTP GET delivery status for each notifier and the notifications awaiting retry
+#[get("/delivery")]
+pub(crate) async fn get_deliveries(
+    deliveries_rwlock: web::Data<Arc<RwLock_Tokio<Deliveries>>>,
+) -> impl Responder {
+    let deliveries = deliveries_rwlock.read().await;
+    HttpResponse::Ok().json(&*deliveries)
+}
+
+/// HTTP GET when each file in each fileset last had a new line
+#[get("/files")]
+pub(crate) async fn get_files(
+    files_last_seen_rwlock: web::Data<Arc<RwLock_Tokio<FilesLastSeen>>>,
+    query: web::Query<FilesQuery>,
+) -> impl Responder {
+    let files_last_seen = files_last_seen_rwlock.read().await;
+    HttpResponse::Ok().json(files_last_seen.files(query.stale))
+}
+
+/// HTTP GET metrics in the Prometheus text exposition format
+#[get("/metrics")]
+pub(crate) async fn get_metrics(
+    filesets_data_rwlock: web::Data<Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>>,
+    files_last_seen_rwlock: web::Data<Arc<RwLock_Tokio<FilesLastSeen>>>,
+    deliveries_rwlock: web::Data<Arc<RwLock_Tokio<Deliveries>>>,
+    escalations_rwlock: web::Data<Arc<RwLock_Tokio<Escalations>>>,
+    silences_rwlock: web::Data<Arc<RwLock_Tokio<Silences>>>,
+    queues: web::Data<Queues>,
+) -> impl Responder {
+    let text = metrics::render(
+        &*filesets_data_rwlock.read().await,
+        &*files_last_seen_rwlock.read().await,
+        &*deliveries_rwlock.read().await,
+        &*escalations_rwlock.read().await,
+        &*silences_rwlock.read().await,
+        &queues,
+    );
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(text)
+}
+
+/// HTTP GET alerts which are escalating until acknowledged
+#[get("/escalation")]
+pub(crate) async fn get_escalations(
+    escalations_rwlock: web::Data<Arc<RwLock_Tokio<Escalations>>>,
+) -> impl Responder {
+    let escalations = escalations_rwlock.read().await;
+    HttpResponse::Ok().json(escalations.open())
+}
+
+/// HTTP POST to acknowledge the alert for a monitor on a fileset, stopping its escalation
+#[post("/fileset/{fileset_id}/monitor/{monitor_id}/ack")]
+pub(crate) async fn acknowledge(
+    escalations_rwlock: web::Data<Arc<RwLock_Tokio<Escalations>>>,
+    queues: web::Data<Queues>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let acknowledged = escalations_rwlock
+        .write()
+        .await
+        .acknowledge(&path.0, &path.1);
+    if let Some(escalation) = acknowledged {
+        println!("Escalation for {} / {} acknowledged", path.0, path.1);
+        let _ = queues
+            .notifier
+            .send(NotifierMessage::Acknowledge(
+                path.0.clone(),
+                path.1.clone(),
+                escalation.event.captures.clone(),
+            ))
+            .await;
+        HttpResponse::Ok().json(escalation)
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "no unacknowledged alert" }))
+    }
+}
@@ -1,12 +1,21 @@
+use crate::delivery::RetryConfig;
+use crate::files_seen::FilesLastSeenConfig;
 use crate::fileset::FileSetId;
 use crate::monitor::MonitorId;
 use crate::notifier::NotifierId;
+use crate::redaction::RedactionConfig;
+use crate::report::{ReportConfig, ReportId};
+use crate::routing::RouteConfig;
+use crate::schedule::ScheduleConfig;
+use crate::silence::MaintenanceWindowId;
+use crate::template::Template;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 use url::Url;
 
 /// Load the config from a file and turn it into a ConfigFile struct
@@ -26,20 +35,83 @@ pub struct ConfigFile {
     pub monitors: HashMap<MonitorId, MonitorConfig>,
     #[serde(with = "serde_yaml::with::singleton_map_recursive")]
     pub notifiers: HashMap<NotifierId, NotifierConfig>,
+    #[serde(default)]
+    pub maintenance_windows: HashMap<MaintenanceWindowId, ScheduleConfig>,
+    /// Rules which send events to extra notifiers, evaluated in order
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Scheduled summaries of event counts
+    #[serde(default)]
+    pub reports: HashMap<ReportId, ReportConfig>,
 }
 
 /// Global configuration options
 #[derive(Serialize, Deserialize)]
 pub struct GlobalConfig {
     pub(crate) notifiers_for_files_last_seen: Vec<NotifierId>,
+    #[serde(default = "default_period_for_files_last_seen")]
     pub(crate) period_for_files_last_seen: usize,
+    /// Schedule and presentation of the files last seen message
+    #[serde(default)]
+    pub(crate) files_last_seen: FilesLastSeenConfig,
+    /// Base URL at which the API can be reached, for links in notifications
+    pub(crate) api_url: Option<String>,
+}
+
+fn default_period_for_files_last_seen() -> usize {
+    60 * 60
 }
 
 /// Configuration for a single set of monitored files
 #[derive(Serialize, Deserialize)]
 pub struct FileSetConfig {
     pub file_globs: Vec<String>,
-    pub monitor_notifier_sets: HashMap<MonitorId, Option<Vec<NotifierId>>>,
+    pub monitor_notifier_sets: HashMap<MonitorId, Option<NotifierSetConfig>>,
+    pub maintenance_windows: Option<Vec<MaintenanceWindowId>>,
+    /// Rules applied to each line before it's matched, stored or sent anywhere
+    #[serde(default)]
+    pub redactions: Vec<RedactionConfig>,
+    /// Notifiers for this fileset's part of the files last seen message, instead of
+    /// global.notifiers_for_files_last_seen
+    pub notifiers_for_files_last_seen: Option<Vec<NotifierId>>,
+}
+
+/// Notifiers for a monitor on a FileSet. Either a plain list, or a list followed by an
+/// escalation chain used while alerts go unacknowledged
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum NotifierSetConfig {
+    Notifiers(Vec<NotifierId>),
+    Escalating {
+        notifiers: Vec<NotifierId>,
+        escalation: Vec<EscalationStepConfig>,
+    },
+}
+
+impl NotifierSetConfig {
+    /// Notifiers which are sent every event
+    pub fn notifier_ids(&self) -> &Vec<NotifierId> {
+        match self {
+            NotifierSetConfig::Notifiers(notifiers) => notifiers,
+            NotifierSetConfig::Escalating { notifiers, .. } => notifiers,
+        }
+    }
+
+    /// Escalation steps, if any
+    pub fn escalation(&self) -> &[EscalationStepConfig] {
+        match self {
+            NotifierSetConfig::Notifiers(_) => &[],
+            NotifierSetConfig::Escalating { escalation, .. } => escalation,
+        }
+    }
+}
+
+/// One step in an escalation chain
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EscalationStepConfig {
+    /// Seconds without acknowledgement after the previous step before this one is notified
+    pub after: usize,
+    pub notifiers: Vec<NotifierId>,
 }
 
 /// Definition of a specific monitor. Can be applied to multiple FileSets
@@ -52,19 +124,358 @@ pub struct MonitorConfig {
     pub keep_lines_after: Option<usize>,
     pub log_counts: bool,
     pub max_wait_before_notify: usize,
+    pub maintenance_windows: Option<Vec<MaintenanceWindowId>>,
+    pub severity: Option<Severity>,
 }
 
-/// Definition of a specific notifier. Currently only Slack/Mattermost webhooks are implemented.
+/// How serious a monitor's events are
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Critical,
+    Error,
+    Warning,
+    Info,
+}
+
+/// Definition of a specific notifier
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum NotifierConfig {
     Webhook(WebhookNotifierConfig),
+    Http(HttpNotifierConfig),
+    Email(EmailNotifierConfig),
+    Command(CommandNotifierConfig),
+    File(FileNotifierConfig),
+    #[serde(rename = "pagerduty")]
+    PagerDuty(PagerDutyNotifierConfig),
+    Teams(TeamsNotifierConfig),
+    Discord(DiscordNotifierConfig),
+    Telegram(TelegramNotifierConfig),
+    Failover(FailoverNotifierConfig),
+}
+
+impl NotifierConfig {
+    /// Options shared by all notifier types
+    pub(crate) fn common(&self) -> &CommonNotifierConfig {
+        match self {
+            NotifierConfig::Webhook(conf) => &conf.common,
+            NotifierConfig::Http(conf) => &conf.common,
+            NotifierConfig::Email(conf) => &conf.common,
+            NotifierConfig::Command(conf) => &conf.common,
+            NotifierConfig::File(conf) => &conf.common,
+            NotifierConfig::PagerDuty(conf) => &conf.common,
+            NotifierConfig::Teams(conf) => &conf.common,
+            NotifierConfig::Discord(conf) => &conf.common,
+            NotifierConfig::Telegram(conf) => &conf.common,
+            NotifierConfig::Failover(conf) => &conf.common,
+        }
+    }
+}
+
+/// Options shared by all notifier types
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommonNotifierConfig {
+    /// Minimum seconds between notifications for each fileset and monitor
+    pub(crate) minimum_interval: Option<usize>,
+    /// How notifications are spaced out while events keep arriving
+    #[serde(default)]
+    pub(crate) backoff: Backoff,
+    /// Collect events arriving inside minimum_interval into a digest rather than skipping them
+    pub(crate) digest: Option<DigestConfig>,
+    pub(crate) maintenance_windows: Option<Vec<MaintenanceWindowId>>,
+    /// How failed deliveries are retried
+    #[serde(default)]
+    pub(crate) retry: RetryConfig,
+    /// Limits on the size of event lines in notifications
+    #[serde(default)]
+    pub(crate) limits: MessageLimitsConfig,
+    /// How event lines are presented. Defaults depend on the notifier type.
+    pub(crate) format: Option<OutputFormat>,
+}
+
+/// How notifications for a fileset and monitor are spaced out while events keep arriving
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backoff {
+    /// At most one notification per minimum_interval
+    #[default]
+    Interval,
+    /// Notify for the 1st, 10th, 100th and so on event in a burst. A burst ends once no
+    /// events have arrived for minimum_interval.
+    Exponential,
+}
+
+/// How event lines are presented in notifications
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// A code block
+    Markdown,
+    Plain,
+    Html,
+}
+
+/// Limits on the size of event lines in notifications. Unset values fall back to defaults
+/// for the notifier type.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct MessageLimitsConfig {
+    /// Maximum characters in the block of event lines
+    pub(crate) max_chars: Option<usize>,
+    /// Lines longer than this many characters are truncated
+    pub(crate) max_line_chars: Option<usize>,
+    /// Split events which are too long into several notifications rather than leaving
+    /// lines out
+    #[serde(default)]
+    pub(crate) split: bool,
+}
+
+impl MessageLimitsConfig {
+    /// These limits, with any unset values taken from `defaults`
+    pub(crate) fn or(&self, defaults: MessageLimitsConfig) -> MessageLimitsConfig {
+        MessageLimitsConfig {
+            max_chars: self.max_chars.or(defaults.max_chars),
+            max_line_chars: self.max_line_chars.or(defaults.max_line_chars),
+            split: self.split,
+        }
+    }
+}
+
+/// Config for digest notifications
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DigestConfig {
+    /// Maximum number of example lines to include per fileset and monitor
+    #[serde(default = "default_digest_examples")]
+    pub(crate) examples: usize,
+}
+
+fn default_digest_examples() -> usize {
+    3
 }
 
 /// Config for a Slack/Mattermost webhook
 #[derive(Serialize, Deserialize, Clone)]
 pub struct WebhookNotifierConfig {
     pub(crate) url: Url,
+    /// Text placed before the event lines in the default message body
+    #[serde(default)]
     pub(crate) template: String,
-    pub(crate) minimum_interval: Option<usize>,
+    /// Template for the whole message body, replacing the default
+    pub(crate) body_template: Option<Template>,
+    /// Send events as attachments, coloured by monitor severity and with fields
+    /// describing the event
+    #[serde(default)]
+    pub(crate) attachments: bool,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// Config for a generic HTTP notifier
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HttpNotifierConfig {
+    pub(crate) url: Url,
+    /// HTTP method, POST by default
+    pub(crate) method: Option<String>,
+    pub(crate) headers: Option<HashMap<String, ConfigValue>>,
+    /// Content-Type header, application/json by default
+    pub(crate) content_type: Option<String>,
+    /// Template for event notification bodies. Defaults to the whole template context as JSON.
+    pub(crate) body_template: Option<Template>,
+    /// Template for other message bodies, given the variable `message`. Defaults to JSON with a `text` field.
+    pub(crate) message_template: Option<Template>,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// Config for an SMTP email notifier
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EmailNotifierConfig {
+    pub(crate) host: String,
+    /// Defaults to 587 for STARTTLS, 465 for TLS and 25 without TLS
+    pub(crate) port: Option<u16>,
+    #[serde(default)]
+    pub(crate) tls: SmtpTls,
+    pub(crate) username: Option<ConfigValue>,
+    pub(crate) password: Option<ConfigValue>,
+    pub(crate) from: String,
+    pub(crate) to: Vec<String>,
+    #[serde(default)]
+    pub(crate) cc: Vec<String>,
+    pub(crate) subject_template: Option<Template>,
+    /// Template for the plain text body of event notifications
+    pub(crate) body_template: Option<Template>,
+    /// Include an HTML rendering of the event lines alongside the plain text
+    #[serde(default)]
+    pub(crate) html: bool,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// How to secure the connection to an SMTP server
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+/// Config for a notifier which runs a program
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CommandNotifierConfig {
+    pub(crate) program: PathBuf,
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+    /// Seconds to wait for the program to exit before killing it
+    #[serde(default = "default_command_timeout")]
+    pub(crate) timeout: usize,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+fn default_command_timeout() -> usize {
+    30
+}
+
+/// Config for a notifier which appends notifications to a file as JSON lines
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileNotifierConfig {
+    pub(crate) path: PathBuf,
+    /// Rotate the file once it reaches this many bytes
+    pub(crate) max_size: Option<u64>,
+    /// Rotate the file after this many seconds
+    pub(crate) rotate_every: Option<usize>,
+    /// Number of rotated files to keep. All are kept if unset.
+    pub(crate) keep: Option<usize>,
+    /// Compress rotated files
+    #[serde(default)]
+    pub(crate) gzip: bool,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// Config for a notifier which raises incidents via the PagerDuty Events API v2
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PagerDutyNotifierConfig {
+    #[serde(default = "default_pagerduty_url")]
+    pub(crate) url: String,
+    /// Where messages other than events are sent, as change events
+    #[serde(default = "default_pagerduty_change_url")]
+    pub(crate) change_url: String,
+    pub(crate) routing_key: ConfigValue,
+    /// Named capture whose value is added to the dedup key, so that e.g. each host or
+    /// job gets its own incident
+    pub(crate) dedup_key_capture: Option<String>,
+    /// Defaults to the monitor's severity, or error if it has none
+    pub(crate) severity: Option<Severity>,
+    pub(crate) summary_template: Option<Template>,
+    /// Resolve the incident when no events have been sent for this many seconds.
+    /// Incidents are left open if unset.
+    pub(crate) resolve_after: Option<usize>,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+fn default_pagerduty_url() -> String {
+    "https://events.pagerduty.com/v2/enqueue".to_string()
+}
+
+fn default_pagerduty_change_url() -> String {
+    "https://events.pagerduty.com/v2/change/enqueue".to_string()
+}
+
+/// Config for a Microsoft Teams incoming webhook, which is sent Adaptive Cards
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TeamsNotifierConfig {
+    pub(crate) url: String,
+    /// Template for the card text, replacing the event lines
+    pub(crate) body_template: Option<Template>,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// Config for a Discord webhook
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiscordNotifierConfig {
+    pub(crate) url: String,
+    /// Name to post as, instead of the webhook's default
+    pub(crate) username: Option<String>,
+    /// Send events as embeds rather than plain message content
+    #[serde(default)]
+    pub(crate) embed: bool,
+    pub(crate) body_template: Option<Template>,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+/// Config for sending messages via a Telegram bot
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TelegramNotifierConfig {
+    #[serde(default = "default_telegram_api_url")]
+    pub(crate) api_url: String,
+    pub(crate) bot_token: ConfigValue,
+    pub(crate) chat_id: String,
+    #[serde(default)]
+    pub(crate) parse_mode: TelegramParseMode,
+    /// Template for event messages. Must produce text valid for parse_mode.
+    pub(crate) body_template: Option<Template>,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// How Telegram should format message text
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum TelegramParseMode {
+    #[default]
+    #[serde(rename = "HTML")]
+    Html,
+    Markdown,
+    MarkdownV2,
+    /// Plain text
+    None,
+}
+
+/// Config for trying a list of other notifiers in turn until one succeeds
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FailoverNotifierConfig {
+    /// Notifier IDs, in order of preference
+    pub(crate) notifiers: Vec<NotifierId>,
+    /// Seconds to skip a notifier for after it fails
+    #[serde(default = "default_failover_cooldown")]
+    pub(crate) cooldown: usize,
+    #[serde(flatten)]
+    pub(crate) common: CommonNotifierConfig,
+}
+
+fn default_failover_cooldown() -> usize {
+    300
+}
+
+/// A value given directly, or read from an environment variable or file when the config is loaded
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigValue {
+    Value(String),
+    Env(String),
+    File(PathBuf),
+}
+
+impl ConfigValue {
+    /// Get the value, reading from the environment or file if necessary
+    pub(crate) fn resolve(&self) -> Result<String, String> {
+        match self {
+            ConfigValue::Value(value) => Ok(value.clone()),
+            ConfigValue::Env(name) => {
+                std::env::var(name).map_err(|e| format!("environment variable {}: {}", name, e))
+            }
+            ConfigValue::File(path) => std::fs::read_to_string(path)
+                .map(|value| value.trim_end().to_string())
+                .map_err(|e| format!("file {:?}: {}", path, e)),
+        }
+    }
 }
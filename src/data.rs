@@ -1,12 +1,17 @@
-use crate::fileset::FileSetId;
+use crate::escalation::Escalations;
+use crate::files_seen::FilesLastSeen;
+use crate::fileset::{FileLine, FileSetId};
 use crate::monitor::MonitorId;
-use crate::notifier::{NotifierId, NotifierMessage};
+use crate::notifier::{NotifierId, NotifierMessage, OpenIncidents};
+use crate::routing::Router;
+use crate::silence::Silences;
 use chrono::offset::TimeZone;
-use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc, Weekday};
-use linemux::Line;
+use chrono::{DateTime, Datelike, Duration, DurationRound, NaiveDate, Timelike, Utc, Weekday};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::fs;
 use std::fs::File;
 use std::io::Read;
@@ -16,11 +21,41 @@ use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::RwLock as RwLock_Tokio;
 use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Everything stored in the data file
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct PersistedData {
+    pub(crate) counts: HashMap<FileSetId, HashMap<MonitorId, EventCounts>>,
+    #[serde(default)]
+    pub(crate) silences: Silences,
+    /// Alerts awaiting acknowledgement
+    #[serde(default)]
+    pub(crate) escalations: Escalations,
+    /// Recent events, with their acknowledgements and notes
+    #[serde(default)]
+    pub(crate) recent_events: HashMap<FileSetId, HashMap<MonitorId, Vec<MonitorEvent>>>,
+    /// Incidents opened by notifiers such as PagerDuty, so they can still be resolved
+    #[serde(default)]
+    pub(crate) open_incidents: HashMap<NotifierId, OpenIncidents>,
+}
+
+/// Data file formats which can be loaded
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DataFileFormat {
+    Current(PersistedData),
+    /// Older data files contained only the counts
+    CountsOnly(HashMap<FileSetId, HashMap<MonitorId, EventCounts>>),
+}
 
 /// Counts and recent events for a single set of monitored files
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct FileSetData {
     pub monitor_data: HashMap<MonitorId, MonitorData>,
+    /// Number of redactions applied to lines since Centinela started
+    #[serde(default)]
+    pub redactions: usize,
 }
 
 /// Counts and recent events for a single monitor for a single set of monitored files
@@ -33,7 +68,7 @@ pub struct MonitorData {
 impl MonitorData {
     /// A line was received on a file that the associated monitor monitors,
     /// we receive it here in case there are previous events still awaiting subsequent lines
-    pub(crate) fn receive_line(&mut self, line: &Line, source: &Path) {
+    pub(crate) fn receive_line(&mut self, line: &FileLine, source: &Path) {
         self.recent_events
             .iter_mut()
             // Get read locks
@@ -52,35 +87,34 @@ impl MonitorData {
     }
 
     /// A Monitor matched a line so we receive it for storage
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn receive_event(
         &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
         ev: MonitorEvent,
         keep_num_events: Option<usize>,
         notifier_ids: Option<Vec<NotifierId>>,
+        router: Arc<Router>,
         notifiers_tx: Sender<NotifierMessage>,
     ) {
+        self.counts.increment(&ev.captures);
+        let ev_arc_mut = Arc::new(RwLock::new(ev));
         // Optionally store the event
         let keep_num_events = match keep_num_events {
             None => 0,
             Some(keep_events) => {
                 // Store
-                self.recent_events.push(Arc::new(RwLock::new(ev)));
+                self.recent_events.push(ev_arc_mut.clone());
                 keep_events
             }
         };
         self.trim(keep_num_events);
-        self.counts.increment();
 
-        // If there are notifiers...
-        if let Some(notifier_ids) = notifier_ids {
-            // Borrow ev back out of self.recent_events
-            let ev_arc_mut = self
-                .recent_events
-                .last()
-                .expect(
-                    "Unable to get last element in Monitor.recent_events despite just adding one.",
-                )
-                .clone();
+        // If there are notifiers, or routes which might add some...
+        if notifier_ids.is_some() || router.has_routes() {
+            let fileset_id = fileset_id.clone();
+            let monitor_id = monitor_id.clone();
             // Spawn a task that will wait for additional lines from the log, if configured, until
             // a timeout is reached, then send an event to the notifiers task
             tokio::spawn(async move {
@@ -102,10 +136,22 @@ impl MonitorData {
                         //println!("Waiting for {} lines...", &ev.awaiting_lines);
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                     } else {
-                        // Notify
-                        let _ = notifiers_tx
-                            .send(NotifierMessage::NotifyEvent(notifier_ids.clone(), ev_clone))
-                            .await;
+                        // Notify, via any notifiers the routes add
+                        if let Some(notifier_ids) = router.notifiers_for_event(
+                            &fileset_id,
+                            &monitor_id,
+                            &ev_clone,
+                            &notifier_ids,
+                        ) {
+                            let _ = notifiers_tx
+                                .send(NotifierMessage::NotifyEvent(
+                                    notifier_ids,
+                                    fileset_id.clone(),
+                                    monitor_id.clone(),
+                                    Box::new(ev_clone),
+                                ))
+                                .await;
+                        }
                         done = true;
                     }
                 }
@@ -114,6 +160,29 @@ impl MonitorData {
         }
     }
 
+    /// A copy of a stored event
+    pub(crate) fn event(&self, event_id: &str) -> Option<MonitorEvent> {
+        self.recent_events
+            .iter()
+            .map(|lock| lock.read().expect("unpoisoned lock"))
+            .find(|ev| ev.id == event_id)
+            .map(|ev| ev.clone())
+    }
+
+    /// Apply a change to a stored event, returning a copy of the changed event
+    pub(crate) fn update_event<F>(&self, event_id: &str, update: F) -> Option<MonitorEvent>
+    where
+        F: FnOnce(&mut MonitorEvent),
+    {
+        let mut ev = self
+            .recent_events
+            .iter()
+            .map(|lock| lock.write().expect("unpoisoned lock"))
+            .find(|ev| ev.id == event_id)?;
+        update(&mut ev);
+        Some(ev.clone())
+    }
+
     /// Remove older events if we have more than keep_num_events
     fn trim(&mut self, keep_num_events: usize) {
         if self.recent_events.len() > keep_num_events {
@@ -135,6 +204,12 @@ pub struct EventCounts {
     pub weeks: HashMap<DateTime<Utc>, usize>,
     pub months: HashMap<DateTime<Utc>, usize>,
     pub years: HashMap<DateTime<Utc>, usize>,
+    /// Counts of each named capture value, as name=value, bucketed by hour
+    #[serde(default)]
+    pub values: HashMap<DateTime<Utc>, HashMap<String, usize>>,
+    /// Events counted since counting began, never trimmed
+    #[serde(default)]
+    pub total: usize,
 }
 
 impl EventCounts {
@@ -145,12 +220,107 @@ impl EventCounts {
     const KEEP_WEEKS: usize = 52;
     const KEEP_MONTHS: usize = 48;
     const KEEP_YEARS: usize = 10;
+    /// Distinct capture values counted per hour, to bound memory use
+    const MAX_VALUES_PER_HOUR: usize = 1000;
+
+    /// Totals for recent periods
+    pub(crate) fn recent(&self) -> RecentCounts {
+        let now = Utc::now();
+        let total_since = |items: &HashMap<DateTime<Utc>, usize>, since: DateTime<Utc>| -> usize {
+            items
+                .iter()
+                .filter(|(k, _v)| **k > since)
+                .map(|(_k, v)| v)
+                .sum()
+        };
+        RecentCounts {
+            last_minute: total_since(&self.seconds, now.sub(Duration::minutes(1))),
+            last_hour: total_since(&self.minutes, now.sub(Duration::hours(1))),
+            last_day: total_since(&self.hours, now.sub(Duration::days(1))),
+            last_week: total_since(&self.days, now.sub(Duration::weeks(1))),
+            last_hour_trend: sparkline(
+                &self.minutes,
+                now.duration_trunc(Duration::minutes(1))
+                    .expect("Truncating to the minute"),
+                Duration::minutes(5),
+                12,
+            ),
+            last_week_trend: sparkline(
+                &self.hours,
+                now.duration_trunc(Duration::hours(1))
+                    .expect("Truncating to the hour"),
+                Duration::hours(6),
+                28,
+            ),
+        }
+    }
+
+    /// Total events between two moments, to the accuracy of the finest buckets still kept
+    /// for that far back
+    pub(crate) fn total_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> usize {
+        self.buckets_for(from)
+            .iter()
+            .filter(|(k, _v)| **k >= from && **k < to)
+            .map(|(_k, v)| v)
+            .sum()
+    }
+
+    /// The most frequent capture values in the hours overlapping two moments
+    pub(crate) fn top_values_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Vec<(String, usize)> {
+        let mut totals: HashMap<&String, usize> = HashMap::new();
+        let from_hour = from.sub(Duration::hours(1));
+        for (_hour, values) in self
+            .values
+            .iter()
+            .filter(|(k, _v)| **k > from_hour && **k < to)
+        {
+            for (value, count) in values {
+                *totals.entry(value).or_default() += count;
+            }
+        }
+        let mut top = totals
+            .into_iter()
+            .map(|(value, count)| (value.clone(), count))
+            .collect::<Vec<(String, usize)>>();
+        top.sort_by(|(a_value, a_count), (b_value, b_count)| {
+            b_count.cmp(a_count).then(a_value.cmp(b_value))
+        });
+        top.truncate(limit);
+        top
+    }
+
+    /// The finest buckets which still cover the given moment
+    fn buckets_for(&self, from: DateTime<Utc>) -> &HashMap<DateTime<Utc>, usize> {
+        let age = Utc::now().sub(from).num_seconds().max(0) as usize;
+        if age <= EventCounts::KEEP_SECONDS {
+            &self.seconds
+        } else if age <= EventCounts::KEEP_MINUTES * 60 {
+            &self.minutes
+        } else if age <= EventCounts::KEEP_HOURS * 60 * 60 {
+            &self.hours
+        } else if age <= EventCounts::KEEP_DAYS * 60 * 60 * 24 {
+            &self.days
+        } else if age <= EventCounts::KEEP_WEEKS * 60 * 60 * 24 * 7 {
+            &self.weeks
+        } else if age <= EventCounts::KEEP_MONTHS * 60 * 60 * 24 * 31 {
+            &self.months
+        } else {
+            &self.years
+        }
+    }
 
     /// Trim all event count types
     fn trim_all(&mut self) {
         EventCounts::trim_older(&mut self.seconds, EventCounts::KEEP_SECONDS);
         EventCounts::trim_older(&mut self.minutes, EventCounts::KEEP_MINUTES * 60);
         EventCounts::trim_older(&mut self.hours, EventCounts::KEEP_HOURS * 60 * 60);
+        let keep_values_since = Utc::now().sub(Duration::hours(EventCounts::KEEP_HOURS as i64));
+        self.values.retain(|k, _v| *k >= keep_values_since);
         EventCounts::trim_older(&mut self.days, EventCounts::KEEP_DAYS * 60 * 60 * 24);
         EventCounts::trim_older(&mut self.weeks, EventCounts::KEEP_WEEKS * 60 * 60 * 24 * 7);
         EventCounts::trim_older(
@@ -169,9 +339,10 @@ impl EventCounts {
         items.retain(|k, _v| *k >= now.sub(Duration::seconds(keep_seconds as i64)));
     }
 
-    /// Increment all current counters
-    fn increment(&mut self) {
+    /// Increment all current counters, and the counts of the event's capture values
+    fn increment(&mut self, captures: &HashMap<String, String>) {
         let now = Utc::now();
+        self.total += 1;
 
         let seconds = Utc
             .with_ymd_and_hms(
@@ -222,6 +393,13 @@ impl EventCounts {
                 self.hours.insert(hours, 1);
             }
         };
+        let values = self.values.entry(hours).or_default();
+        for (name, value) in captures {
+            let key = format!("{}={}", name, value);
+            if values.len() < EventCounts::MAX_VALUES_PER_HOUR || values.contains_key(&key) {
+                *values.entry(key).or_default() += 1;
+            }
+        }
 
         let days = Utc
             .with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
@@ -275,11 +453,69 @@ impl EventCounts {
     }
 }
 
+/// Event totals for recent periods. Each is the total of the buckets which started
+/// within the period, so the oldest bucket is excluded.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct RecentCounts {
+    pub last_minute: usize,
+    pub last_hour: usize,
+    pub last_day: usize,
+    pub last_week: usize,
+    /// Sparkline of the last hour in 5 minute steps, oldest first
+    pub last_hour_trend: String,
+    /// Sparkline of the last week in 6 hour steps, oldest first
+    pub last_week_trend: String,
+}
+
+/// A text sparkline such as "▁▂▅█▃" of `steps` consecutive totals of `step` length, the
+/// last of which includes the bucket starting at `latest`. Steps without events are "▁" and
+/// the rest are scaled so the busiest is "█".
+fn sparkline(
+    items: &HashMap<DateTime<Utc>, usize>,
+    latest: DateTime<Utc>,
+    step: Duration,
+    steps: i32,
+) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    // Buckets are keyed by their start, so the last step ends just after the latest one
+    let end = latest + Duration::seconds(1);
+    let totals = (0..steps)
+        .rev()
+        .map(|steps_back| {
+            let to = end - step * steps_back;
+            let from = to - step;
+            items
+                .iter()
+                .filter(|(k, _v)| **k >= from && **k < to)
+                .map(|(_k, v)| v)
+                .sum()
+        })
+        .collect::<Vec<usize>>();
+    let max = totals.iter().copied().max().unwrap_or(0);
+    totals
+        .iter()
+        .map(|total| match total {
+            0 => BARS[0],
+            _ if max == 1 => BARS[7],
+            total => BARS[1 + (total - 1) * 6 / (max - 1)],
+        })
+        .collect()
+}
+
+/// Newtype
+pub(crate) type EventId = String;
+
 /// A particular monitor match event
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MonitorEvent {
+    /// Stable ID for referring to the event through the API
+    #[serde(default = "new_event_id")]
+    pub id: EventId,
     /// Matching log lines
     pub lines: Vec<LogLine>,
+    /// Values of named capture groups in the monitor's regex
+    #[serde(default)]
+    pub captures: HashMap<String, String>,
     /// How many additional lines should be collected
     pub awaiting_lines: usize,
     /// Which files the lines we're waiting from should be found
@@ -287,52 +523,53 @@ pub struct MonitorEvent {
     /// Timeout after which a notification will be sent even if we're still waiting for
     /// additional lines
     pub notify_by: DateTime<Utc>,
+    /// Who acknowledged the event, and when
+    #[serde(default)]
+    pub acknowledgement: Option<Acknowledgement>,
+    /// Notes added through the API, oldest first
+    #[serde(default)]
+    pub notes: Vec<EventNote>,
 }
 
-impl MonitorEvent {
-    /// Get all stored lines for this event as markdown, highlighting the line containing the event itself
-    pub(crate) fn get_lines_as_markdown(&self) -> String {
-        "\n```".to_string()
-            + self
-                .lines
-                .clone()
-                .into_iter()
-                .map(|i| {
-                    if i.is_event_line {
-                        let wrap_upper = String::from_utf8(vec![
-                            b'v';
-                            if i.to_string().len() < 100 {
-                                i.to_string().len()
-                            } else {
-                                100
-                            }
-                        ])
-                        .expect("Failed to create upper wrapper text");
-                        let wrap_lower = String::from_utf8(vec![
-                            b'^';
-                            if i.to_string().len() < 100 {
-                                i.to_string().len()
-                            } else {
-                                100
-                            }
-                        ])
-                        .expect("Failed to create lower wrapper text");
-                        "\n".to_string()
-                            + wrap_upper.as_str()
-                            + "\n"
-                            + i.to_string().as_str()
-                            + "\n"
-                            + wrap_lower.as_str()
-                            + "\n"
-                    } else {
-                        i.to_string()
-                    }
-                })
-                .collect::<Vec<String>>()
-                .join("\n")
-                .as_str()
-            + "\n```\n"
-    }
+/// Generate a new ID for an event
+pub(crate) fn new_event_id() -> EventId {
+    Uuid::new_v4().to_string()
+}
+
+/// Request body for acknowledging an event
+#[derive(Deserialize)]
+pub struct NewAcknowledgement {
+    pub by: String,
+}
+
+/// Request body for adding a note to an event
+#[derive(Deserialize)]
+pub struct NewNote {
+    pub by: String,
+    pub text: String,
+}
+
+/// Acknowledgement of an event through the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Acknowledgement {
+    pub by: String,
+    pub at: DateTime<Utc>,
+}
+
+/// A free-text note attached to an event through the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventNote {
+    pub by: String,
+    pub at: DateTime<Utc>,
+    pub text: String,
+}
+
+/// Escape text for inclusion in HTML
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 /// A single line from a log file
@@ -343,16 +580,16 @@ pub struct LogLine {
     pub is_event_line: bool,
 }
 
-impl ToString for LogLine {
-    fn to_string(&self) -> String {
-        self.date.to_string() + " " + self.line.as_str()
+impl Display for LogLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.date, self.line)
     }
 }
 
 /// Messages that the data store task listens for
 #[derive(Debug)]
 pub(crate) enum DataStoreMessage {
-    ReceiveLine(FileSetId, MonitorId, Line),
+    ReceiveLine(FileSetId, MonitorId, FileLine),
     ReceiveEvent(
         FileSetId,
         MonitorId,
@@ -361,16 +598,22 @@ pub(crate) enum DataStoreMessage {
         Option<Vec<NotifierId>>,
     ),
     FileSeen(FileSetId, String),
-    NotifyFilesSeen(Vec<NotifierId>),
+    Redacted(FileSetId, usize),
+    NotifyFilesSeen,
     Persist,
     Shutdown,
 }
 
 /// Start the data store task.
 /// This loops listening for events until it's instructed to shut down.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn start_task(
     filesets_data_rwlock: Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>,
-    mut files_last_seen_data: HashMap<FileSetId, HashMap<String, DateTime<Utc>>>,
+    files_last_seen_rwlock: Arc<RwLock_Tokio<FilesLastSeen>>,
+    silences_rwlock: Arc<RwLock_Tokio<Silences>>,
+    escalations_rwlock: Arc<RwLock_Tokio<Escalations>>,
+    open_incidents_rwlock: Arc<RwLock_Tokio<HashMap<NotifierId, OpenIncidents>>>,
+    router: Arc<Router>,
     notifiers_tx: Sender<NotifierMessage>,
     data_file_path: String,
 ) -> (Sender<DataStoreMessage>, JoinHandle<()>) {
@@ -396,47 +639,47 @@ pub(crate) async fn start_task(
                     let monitor_data =
                         fetch_monitor_data(&mut filesets_data, &file_set_id, &monitor_id);
                     monitor_data
-                        .receive_event(ev, keep_num_events, notifier_ids, notifiers_tx.clone())
+                        .receive_event(
+                            &file_set_id,
+                            &monitor_id,
+                            ev,
+                            keep_num_events,
+                            notifier_ids,
+                            router.clone(),
+                            notifiers_tx.clone(),
+                        )
                         .await;
                 }
                 DataStoreMessage::FileSeen(fileset_id, file_path) => {
-                    let inner = files_last_seen_data.entry(fileset_id).or_default();
-                    inner.insert(file_path, Utc::now());
+                    files_last_seen_rwlock
+                        .write()
+                        .await
+                        .record(fileset_id, file_path);
                 }
-                DataStoreMessage::NotifyFilesSeen(notifier_ids) => {
-                    let message = "Files last seen: \n\n".to_string() + {
-                        files_last_seen_data
-                            .iter()
-                            .map(|(k, v)| {
-                                let mut inner_message_lines = v
-                                    .iter()
-                                    .map(|(inner_k, inner_v)| {
-                                        format!(
-                                            "\t{} : {}s ago",
-                                            inner_k,
-                                            (Utc::now().timestamp() - inner_v.timestamp())
-                                        )
-                                    })
-                                    .collect::<Vec<String>>();
-                                inner_message_lines.sort();
-                                format!(
-                                    "{}:\n{}",
-                                    k,
-                                    inner_message_lines.iter().fold(String::new(), |acc, line| {
-                                        acc + line.as_str() + "\n"
-                                    })
-                                )
-                            })
-                            .fold(String::new(), |acc, line| acc + line.as_str() + "\n")
-                            .as_str()
-                    };
-
-                    let _ = notifiers_tx
-                        .send(NotifierMessage::NotifyMessage(notifier_ids, message))
-                        .await;
+                DataStoreMessage::Redacted(fileset_id, redactions) => {
+                    if let Some(fileset_data) =
+                        filesets_data_rwlock.write().await.get_mut(&fileset_id)
+                    {
+                        fileset_data.redactions += redactions;
+                    }
+                }
+                DataStoreMessage::NotifyFilesSeen => {
+                    let messages = files_last_seen_rwlock.read().await.messages();
+                    for (notifier_ids, message) in messages {
+                        let _ = notifiers_tx
+                            .send(NotifierMessage::NotifyMessage(notifier_ids, message))
+                            .await;
+                    }
                 }
                 DataStoreMessage::Persist => {
-                    persist_data(&filesets_data_rwlock, data_file_path.as_str()).await
+                    persist_data(
+                        &filesets_data_rwlock,
+                        &silences_rwlock,
+                        &escalations_rwlock,
+                        &open_incidents_rwlock,
+                        data_file_path.as_str(),
+                    )
+                    .await
                 }
                 DataStoreMessage::Shutdown => break,
             }
@@ -462,37 +705,66 @@ fn fetch_monitor_data<'a>(
     monitor_data
 }
 
-/// Save counts data to disk
+/// Save counts data, recent events, silences, escalations and open incidents to disk
 async fn persist_data(
     filesets_data_rwlock: &Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>,
+    silences_rwlock: &Arc<RwLock_Tokio<Silences>>,
+    escalations_rwlock: &Arc<RwLock_Tokio<Escalations>>,
+    open_incidents_rwlock: &Arc<RwLock_Tokio<HashMap<NotifierId, OpenIncidents>>>,
     data_file_path: &str,
 ) {
     let data = filesets_data_rwlock.read().await;
     let mut save_data: HashMap<FileSetId, HashMap<MonitorId, EventCounts>> = Default::default();
+    let mut recent_events: HashMap<FileSetId, HashMap<MonitorId, Vec<MonitorEvent>>> =
+        Default::default();
     for (fileset_id, fileset_data) in &data as &HashMap<FileSetId, FileSetData> {
         let mut fileset_counts: HashMap<MonitorId, EventCounts> = Default::default();
+        let mut fileset_events: HashMap<MonitorId, Vec<MonitorEvent>> = Default::default();
         for (monitor_id, monitor_data) in &fileset_data.monitor_data {
             let counts = monitor_data.counts.clone();
             fileset_counts.insert(monitor_id.clone(), counts);
+            let events = monitor_data
+                .recent_events
+                .iter()
+                .map(|lock| lock.read().expect("unpoisoned lock").clone())
+                .collect();
+            fileset_events.insert(monitor_id.clone(), events);
         }
         save_data.insert(fileset_id.clone(), fileset_counts);
+        recent_events.insert(fileset_id.clone(), fileset_events);
     }
-    let data_str = serde_json::to_string(&save_data).expect("Failed to encode data-store to JSON");
     // Early drop to release the lock
     drop(data);
+    let silences = {
+        let mut silences = silences_rwlock.write().await;
+        silences.remove_expired();
+        silences.clone()
+    };
+    let data_str = serde_json::to_string(&PersistedData {
+        counts: save_data,
+        silences,
+        escalations: escalations_rwlock.read().await.clone(),
+        recent_events,
+        open_incidents: open_incidents_rwlock.read().await.clone(),
+    })
+    .expect("Failed to encode data-store to JSON");
     match fs::write(data_file_path, data_str) {
         Ok(_) => println!("Wrote data file: {}", &data_file_path),
         Err(err) => println!("Error writing data file: {}", err),
     };
 }
 
-/// Load counts data from disk
-pub(crate) fn load_data_from_file(
-    data_file_path: &str,
-) -> Result<HashMap<FileSetId, HashMap<MonitorId, EventCounts>>, Box<dyn Error>> {
+/// Load counts data, recent events, silences, escalations and open incidents from disk
+pub(crate) fn load_data_from_file(data_file_path: &str) -> Result<PersistedData, Box<dyn Error>> {
     let mut file = File::open(data_file_path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    let stats = serde_json::from_str(&contents)?;
-    Ok(stats)
+    let data = match serde_json::from_str(&contents)? {
+        DataFileFormat::Current(data) => data,
+        DataFileFormat::CountsOnly(counts) => PersistedData {
+            counts,
+            ..Default::default()
+        },
+    };
+    Ok(data)
 }
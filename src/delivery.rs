@@ -0,0 +1,404 @@
+use crate::notifier::NotifierId;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use uuid::Uuid;
+
+/// Newtype
+pub(crate) type DeliveryId = String;
+
+/// Longest delay in seconds allowed between attempts. Larger max_backoff values are clamped.
+const MAX_BACKOFF: usize = 7 * 24 * 60 * 60;
+
+/// Most queued deliveries retried on each tick of the notifier task, so that a full outbox
+/// can't hold up fresh notifications for long
+pub(crate) const MAX_RETRIES_PER_TICK: usize = 10;
+
+/// How failed deliveries are retried
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first, before giving up
+    #[serde(default = "default_max_attempts")]
+    pub(crate) max_attempts: usize,
+    /// Delay in seconds before the first retry. Doubles with each subsequent attempt.
+    #[serde(default = "default_initial_backoff")]
+    pub(crate) initial_backoff: usize,
+    /// Maximum delay in seconds between attempts, up to a week
+    #[serde(
+        default = "default_max_backoff",
+        deserialize_with = "deserialize_max_backoff"
+    )]
+    pub(crate) max_backoff: usize,
+}
+
+fn default_max_attempts() -> usize {
+    8
+}
+
+fn default_initial_backoff() -> usize {
+    10
+}
+
+fn default_max_backoff() -> usize {
+    60 * 60
+}
+
+/// Read max_backoff, clamping it to MAX_BACKOFF so backoff calculations can't overflow
+fn deserialize_max_backoff<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(usize::deserialize(deserializer)?.min(MAX_BACKOFF))
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: default_max_attempts(),
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Exponential backoff with jitter for the given number of attempts so far. A delay requested
+    /// by the receiver (e.g. via Retry-After) takes precedence, up to max_backoff.
+    pub(crate) fn next_attempt(
+        &self,
+        attempts: usize,
+        retry_after: Option<Duration>,
+    ) -> DateTime<Utc> {
+        let max_backoff = self.max_backoff_duration();
+        let delay = match retry_after {
+            Some(retry_after) => retry_after.min(max_backoff),
+            None => {
+                let exponent = attempts.saturating_sub(1).min(31) as u32;
+                let backoff = self
+                    .initial_backoff
+                    .saturating_mul(2usize.saturating_pow(exponent))
+                    .min(self.max_backoff)
+                    .max(1);
+                // Somewhere between half and all of the backoff, so retries from many
+                // failures don't all land at once
+                let millis = rand::thread_rng()
+                    .gen_range(backoff.saturating_mul(500)..=backoff.saturating_mul(1000));
+                i64::try_from(millis)
+                    .ok()
+                    .and_then(Duration::try_milliseconds)
+                    .unwrap_or(max_backoff)
+            }
+        };
+        let now = Utc::now();
+        now.checked_add_signed(delay)
+            .or_else(|| now.checked_add_signed(max_backoff))
+            .unwrap_or(now)
+    }
+
+    /// max_backoff as a Duration, saturating if it's too large to represent
+    fn max_backoff_duration(&self) -> Duration {
+        i64::try_from(self.max_backoff)
+            .ok()
+            .and_then(Duration::try_seconds)
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+/// Why a delivery failed
+#[derive(Debug)]
+pub(crate) struct DeliveryError {
+    pub(crate) message: String,
+    /// Delay requested by the receiver before trying again
+    pub(crate) retry_after: Option<Duration>,
+    /// Retrying won't help, e.g. the receiver rejected the payload
+    pub(crate) permanent: bool,
+}
+
+impl DeliveryError {
+    /// A failure which might succeed if retried
+    pub(crate) fn transient(message: String) -> DeliveryError {
+        DeliveryError {
+            message,
+            retry_after: None,
+            permanent: false,
+        }
+    }
+
+    /// A failure which will recur if retried
+    pub(crate) fn permanent(message: String) -> DeliveryError {
+        DeliveryError {
+            message,
+            retry_after: None,
+            permanent: true,
+        }
+    }
+}
+
+impl Display for DeliveryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// HTTP client for back-ends which deliver notifications over HTTP
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Turn the result of an HTTP request into a delivery result. 429 and 5xx responses
+/// are retryable, other non-success responses aren't.
+pub(crate) fn check_response(
+    res: Result<reqwest::Response, reqwest::Error>,
+) -> Result<(), DeliveryError> {
+    // Leave the URL out of the error, since some (e.g. Telegram's) contain a secret
+    let res =
+        res.map_err(|e| DeliveryError::transient(format!("Request failed: {}", e.without_url())))?;
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    Err(DeliveryError {
+        message: format!("Received HTTP status {}", status),
+        retry_after,
+        permanent: !(status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS),
+    })
+}
+
+/// Parse a Retry-After header, which is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<i64>() {
+        return Duration::try_seconds(seconds).filter(|delay| *delay >= Duration::zero());
+    }
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc) - Utc::now())
+        .filter(|delay| *delay > Duration::zero())
+}
+
+/// A notification which hasn't been delivered yet and is waiting to be retried
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingDelivery {
+    pub id: DeliveryId,
+    pub notifier_id: NotifierId,
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+    pub attempts: usize,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: String,
+}
+
+/// Delivery counters for a single notifier
+#[derive(Serialize, Default, Debug)]
+pub struct DeliveryStats {
+    pub sent: usize,
+    pub failed_attempts: usize,
+    /// Notifications abandoned after running out of attempts or a permanent failure
+    pub abandoned: usize,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// Event notifications which weren't sent, by reason
+    pub skipped: HashMap<SkipReason, usize>,
+}
+
+/// Why an event notification wasn't sent
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Silenced,
+    MaintenanceWindow,
+    /// Skipped or held back for a digest due to minimum_interval
+    RateLimited,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            SkipReason::Silenced => "silenced",
+            SkipReason::MaintenanceWindow => "maintenance_window",
+            SkipReason::RateLimited => "rate_limited",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Delivery status for all notifiers, plus the outbox of notifications awaiting retry
+#[derive(Serialize, Default, Debug)]
+pub struct Deliveries {
+    pub notifiers: HashMap<NotifierId, DeliveryStats>,
+    pub outbox: Vec<PendingDelivery>,
+}
+
+impl Deliveries {
+    /// Record a successful delivery
+    pub(crate) fn record_success(&mut self, notifier_id: &NotifierId) {
+        let stats = self.notifiers.entry(notifier_id.clone()).or_default();
+        stats.sent += 1;
+        stats.last_success = Some(Utc::now());
+    }
+
+    /// Record a failed delivery attempt
+    pub(crate) fn record_failure(&mut self, notifier_id: &NotifierId, error: &DeliveryError) {
+        let stats = self.notifiers.entry(notifier_id.clone()).or_default();
+        stats.failed_attempts += 1;
+        stats.last_failure = Some(Utc::now());
+        stats.last_error = Some(error.message.clone());
+    }
+
+    /// Record giving up on a notification
+    pub(crate) fn record_abandoned(&mut self, notifier_id: &NotifierId) {
+        self.notifiers
+            .entry(notifier_id.clone())
+            .or_default()
+            .abandoned += 1;
+    }
+
+    /// Record an event notification not being sent
+    pub(crate) fn record_skipped(&mut self, notifier_id: &NotifierId, reason: SkipReason) {
+        *self
+            .notifiers
+            .entry(notifier_id.clone())
+            .or_default()
+            .skipped
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    /// Add a failed notification to the outbox
+    pub(crate) fn enqueue(
+        &mut self,
+        notifier_id: &NotifierId,
+        payload: String,
+        error: &DeliveryError,
+        next_attempt: DateTime<Utc>,
+    ) {
+        self.outbox.push(PendingDelivery {
+            id: Uuid::new_v4().to_string(),
+            notifier_id: notifier_id.clone(),
+            payload,
+            created_at: Utc::now(),
+            attempts: 1,
+            next_attempt,
+            last_error: error.message.clone(),
+        });
+    }
+
+    /// Remove and return up to `limit` of the deliveries due for another attempt, longest
+    /// overdue first
+    pub(crate) fn take_due(&mut self, limit: usize) -> Vec<PendingDelivery> {
+        let now = Utc::now();
+        let (mut due, mut waiting): (Vec<PendingDelivery>, Vec<PendingDelivery>) =
+            std::mem::take(&mut self.outbox)
+                .into_iter()
+                .partition(|delivery| delivery.next_attempt <= now);
+        due.sort_by_key(|delivery| delivery.next_attempt);
+        if due.len() > limit {
+            waiting.extend(due.split_off(limit));
+        }
+        self.outbox = waiting;
+        due
+    }
+}
+
+/// Save the outbox to disk so undelivered notifications survive restarts
+pub(crate) fn persist_outbox(outbox: &[PendingDelivery], outbox_file_path: &str) {
+    let data_str = serde_json::to_string(outbox).expect("Failed to encode outbox to JSON");
+    if let Err(err) = fs::write(outbox_file_path, data_str) {
+        println!("Error writing outbox file: {}", err);
+    }
+}
+
+/// Load the outbox from disk
+pub(crate) fn load_outbox_from_file(
+    outbox_file_path: &str,
+) -> Result<Vec<PendingDelivery>, Box<dyn Error>> {
+    let contents = fs::read_to_string(outbox_file_path)?;
+    let outbox = serde_json::from_str(&contents)?;
+    Ok(outbox)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::seconds(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::zero()));
+    }
+
+    #[test]
+    fn parse_retry_after_rejects_negative_and_huge_values() {
+        assert_eq!(parse_retry_after("-5"), None);
+        assert_eq!(parse_retry_after("9000000000000000000"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn parse_retry_after_http_date() {
+        let date = (Utc::now() + Duration::seconds(600)).to_rfc2822();
+        let delay = parse_retry_after(&date).unwrap();
+        assert!(delay > Duration::seconds(590) && delay <= Duration::seconds(600));
+        // Dates in the past don't delay the retry
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn next_attempt_clamps_retry_after_to_max_backoff() {
+        let config = RetryConfig::default();
+        let before = Utc::now();
+        // Parses to a valid Duration, but one far too large to add to now
+        let huge = parse_retry_after("9000000000000").unwrap();
+        let next_attempt = config.next_attempt(1, Some(huge));
+        assert!(next_attempt > before);
+        assert!(next_attempt <= Utc::now() + Duration::seconds(config.max_backoff as i64));
+    }
+
+    #[test]
+    fn max_backoff_is_clamped_when_loaded() {
+        let config: RetryConfig =
+            serde_json::from_value(serde_json::json!({ "max_backoff": usize::MAX })).unwrap();
+        assert_eq!(config.max_backoff, MAX_BACKOFF);
+        let config = RetryConfig {
+            initial_backoff: usize::MAX,
+            ..config
+        };
+        let next_attempt = config.next_attempt(40, None);
+        assert!(next_attempt <= Utc::now() + Duration::seconds(MAX_BACKOFF as i64));
+    }
+
+    #[test]
+    fn take_due_takes_longest_overdue_first_up_to_limit() {
+        let mut deliveries = Deliveries::default();
+        let now = Utc::now();
+        for (payload, next_attempt) in [
+            ("later", now + Duration::seconds(60)),
+            ("recent", now - Duration::seconds(10)),
+            ("oldest", now - Duration::seconds(30)),
+            ("older", now - Duration::seconds(20)),
+        ] {
+            let error = DeliveryError::transient("failed".to_string());
+            deliveries.enqueue(&"n".to_string(), payload.to_string(), &error, next_attempt);
+        }
+        let due = deliveries.take_due(2);
+        let payloads = due
+            .iter()
+            .map(|delivery| delivery.payload.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(payloads, vec!["oldest", "older"]);
+        assert_eq!(deliveries.outbox.len(), 2);
+    }
+}
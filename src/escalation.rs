@@ -0,0 +1,223 @@
+use crate::config::EscalationStepConfig;
+use crate::data::MonitorEvent;
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::NotifierId;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Escalation chains configured for monitors on filesets
+#[derive(Default)]
+pub(crate) struct EscalationPolicies {
+    policies: HashMap<(FileSetId, MonitorId), Vec<EscalationStepConfig>>,
+}
+
+impl EscalationPolicies {
+    /// Attach an escalation chain to a monitor on a fileset
+    pub(crate) fn attach(
+        &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        steps: &[EscalationStepConfig],
+    ) {
+        for step in steps {
+            if step_due_at(Utc::now(), step).is_none() {
+                panic!(
+                    "Escalation step after {} seconds for {:?} / {:?} is too long",
+                    step.after, fileset_id, monitor_id
+                );
+            }
+        }
+        if !steps.is_empty() {
+            self.policies
+                .insert((fileset_id.clone(), monitor_id.clone()), steps.to_vec());
+        }
+    }
+
+    /// Check that all notifier IDs referenced by escalation steps exist
+    pub(crate) fn validate<T>(&self, notifiers: &HashMap<NotifierId, T>) {
+        for ((fileset_id, monitor_id), steps) in &self.policies {
+            for notifier_id in steps.iter().flat_map(|step| &step.notifiers) {
+                if !notifiers.contains_key(notifier_id) {
+                    panic!(
+                        "Invalid notifier ID {:?} in escalation for {:?} / {:?}",
+                        notifier_id, fileset_id, monitor_id
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, fileset_id: &FileSetId, monitor_id: &MonitorId) -> bool {
+        self.policies
+            .contains_key(&(fileset_id.clone(), monitor_id.clone()))
+    }
+
+    fn get(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+    ) -> Option<&[EscalationStepConfig]> {
+        self.policies
+            .get(&(fileset_id.clone(), monitor_id.clone()))
+            .map(|steps| steps.as_slice())
+    }
+}
+
+/// When a step is due, given when the previous one was notified. None if it's too far off to
+/// represent.
+fn step_due_at(
+    last_notified_at: DateTime<Utc>,
+    step: &EscalationStepConfig,
+) -> Option<DateTime<Utc>> {
+    i64::try_from(step.after)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .and_then(|after| last_notified_at.checked_add_signed(after))
+}
+
+/// An alert which hasn't been acknowledged, working its way along an escalation chain
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Escalation {
+    pub fileset_id: FileSetId,
+    pub monitor_id: MonitorId,
+    /// When the first notification was sent
+    pub started_at: DateTime<Utc>,
+    /// Number of escalation steps notified so far
+    pub steps_notified: usize,
+    /// When the most recent step, or the first notification, was sent
+    pub last_notified_at: DateTime<Utc>,
+    /// Events seen since the escalation started
+    pub events: usize,
+    /// The most recent event, which is what each step's notifiers are sent
+    pub event: MonitorEvent,
+}
+
+/// Escalations awaiting acknowledgement, at most one per monitor on each fileset
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(transparent)]
+pub struct Escalations {
+    escalations: Vec<Escalation>,
+}
+
+impl Escalations {
+    /// Start an escalation for an event, or update the event of one already under way
+    pub(crate) fn start_or_update(
+        &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        event: &MonitorEvent,
+    ) {
+        if let Some(escalation) = self.find_mut(fileset_id, monitor_id) {
+            escalation.events += 1;
+            escalation.event = event.clone();
+            return;
+        }
+        let now = Utc::now();
+        println!("Escalation started for {} / {}", fileset_id, monitor_id);
+        self.escalations.push(Escalation {
+            fileset_id: fileset_id.clone(),
+            monitor_id: monitor_id.clone(),
+            started_at: now,
+            steps_notified: 0,
+            last_notified_at: now,
+            events: 1,
+            event: event.clone(),
+        });
+    }
+
+    /// Acknowledge the alert for a monitor on a fileset, ending its escalation. Returns the
+    /// escalation if there was one.
+    pub(crate) fn acknowledge(
+        &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+    ) -> Option<Escalation> {
+        let index = self.escalations.iter().position(|escalation| {
+            &escalation.fileset_id == fileset_id && &escalation.monitor_id == monitor_id
+        })?;
+        Some(self.escalations.remove(index))
+    }
+
+    /// Advance escalations whose next step is due, returning the notifiers for each step
+    /// along with the escalation
+    pub(crate) fn take_due_steps(
+        &mut self,
+        policies: &EscalationPolicies,
+    ) -> Vec<(Vec<NotifierId>, Escalation)> {
+        let now = Utc::now();
+        let mut due = vec![];
+        for escalation in &mut self.escalations {
+            let steps = match policies.get(&escalation.fileset_id, &escalation.monitor_id) {
+                Some(steps) => steps,
+                None => continue,
+            };
+            if let Some(step) = steps.get(escalation.steps_notified) {
+                if step_due_at(escalation.last_notified_at, step).is_some_and(|due| due <= now) {
+                    escalation.steps_notified += 1;
+                    escalation.last_notified_at = now;
+                    due.push((step.notifiers.clone(), escalation.clone()));
+                }
+            }
+        }
+        due
+    }
+
+    /// Drop escalations for monitors which no longer have an escalation chain, such as
+    /// after a config change
+    pub(crate) fn retain_configured(&mut self, policies: &EscalationPolicies) {
+        self.escalations
+            .retain(|escalation| policies.contains(&escalation.fileset_id, &escalation.monitor_id));
+    }
+
+    /// All escalations awaiting acknowledgement, oldest first
+    pub(crate) fn open(&self) -> Vec<&Escalation> {
+        let mut open = self.escalations.iter().collect::<Vec<&Escalation>>();
+        open.sort_by_key(|escalation| escalation.started_at);
+        open
+    }
+
+    fn find_mut(
+        &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+    ) -> Option<&mut Escalation> {
+        self.escalations.iter_mut().find(|escalation| {
+            &escalation.fileset_id == fileset_id && &escalation.monitor_id == monitor_id
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(after: usize) -> EscalationStepConfig {
+        EscalationStepConfig {
+            after,
+            notifiers: vec!["pager".to_string()],
+        }
+    }
+
+    #[test]
+    fn step_due_at_handles_huge_delays() {
+        let now = Utc::now();
+        assert_eq!(
+            step_due_at(now, &step(60)),
+            Some(now + Duration::seconds(60))
+        );
+        assert_eq!(step_due_at(now, &step(usize::MAX)), None);
+        assert_eq!(step_due_at(now, &step(i64::MAX as usize / 1000)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "is too long")]
+    fn attach_rejects_huge_delays() {
+        EscalationPolicies::default().attach(
+            &"web".to_string(),
+            &"errors".to_string(),
+            &[step(300), step(usize::MAX)],
+        );
+    }
+}
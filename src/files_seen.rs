@@ -0,0 +1,360 @@
+use crate::fileset::FileSetId;
+use crate::notifier::NotifierId;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Options for the files last seen message
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FilesLastSeenConfig {
+    /// Cron expression (sec min hour day-of-month month day-of-week [year]) for when the
+    /// message is sent. Replaces global.period_for_files_last_seen when set.
+    pub cron: Option<cron::Schedule>,
+    /// Seconds to wait after startup before the first message, when not using cron
+    #[serde(default = "default_initial_delay")]
+    pub initial_delay: usize,
+    /// Only list files which haven't been seen for at least this many seconds
+    pub stale_after: Option<usize>,
+    /// Show durations such as "2h 5m ago" and the time each file was last seen, rather than
+    /// a number of seconds
+    #[serde(default)]
+    pub humanise: bool,
+    /// Timezone for the cron expression and the times shown. Defaults to UTC.
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+}
+
+impl Default for FilesLastSeenConfig {
+    fn default() -> Self {
+        FilesLastSeenConfig {
+            cron: None,
+            initial_delay: default_initial_delay(),
+            stale_after: None,
+            humanise: false,
+            timezone: default_timezone(),
+        }
+    }
+}
+
+fn default_initial_delay() -> usize {
+    60
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+/// When a file was last seen, as returned by the API
+#[derive(Serialize)]
+pub(crate) struct FileSeen {
+    pub(crate) path: String,
+    /// None if the file has never been seen
+    pub(crate) last_seen: Option<DateTime<Utc>>,
+    pub(crate) seconds_ago: Option<i64>,
+    /// Lines read from the file since Centinela started
+    pub(crate) lines: usize,
+    /// Whether the file has never been seen, or hasn't been seen for longer than stale_after if
+    /// that's set
+    pub(crate) stale: bool,
+}
+
+/// Query string for GET /files
+#[derive(Deserialize)]
+pub(crate) struct FilesQuery {
+    /// Only return stale files
+    #[serde(default)]
+    pub(crate) stale: bool,
+}
+
+/// When a file last had a new line, and how many it's had. Files which haven't had a line
+/// since startup were last seen when they were last modified, if known.
+struct SeenFile {
+    last_seen: Option<DateTime<Utc>>,
+    lines: usize,
+}
+
+/// When each file in each fileset last had a new line, and who to tell about it
+pub(crate) struct FilesLastSeen {
+    config: FilesLastSeenConfig,
+    seen: HashMap<FileSetId, HashMap<String, SeenFile>>,
+    /// Notifiers for filesets which don't have their own
+    notifier_ids: Vec<NotifierId>,
+    fileset_notifier_ids: HashMap<FileSetId, Vec<NotifierId>>,
+}
+
+impl FilesLastSeen {
+    pub(crate) fn new(
+        config: FilesLastSeenConfig,
+        notifier_ids: Vec<NotifierId>,
+        fileset_notifier_ids: HashMap<FileSetId, Vec<NotifierId>>,
+    ) -> FilesLastSeen {
+        FilesLastSeen {
+            config,
+            seen: HashMap::new(),
+            notifier_ids,
+            fileset_notifier_ids,
+        }
+    }
+
+    /// Check that all notifier IDs for the files last seen message exist
+    pub(crate) fn validate<T>(&self, notifiers: &HashMap<NotifierId, T>) {
+        for notifier_id in &self.notifier_ids {
+            if !notifiers.contains_key(notifier_id) {
+                panic!(
+                    "Invalid notifier ID {:?} in global.notifiers_for_files_last_seen",
+                    notifier_id
+                );
+            }
+        }
+        for (fileset_id, notifier_ids) in &self.fileset_notifier_ids {
+            for notifier_id in notifier_ids {
+                if !notifiers.contains_key(notifier_id) {
+                    panic!(
+                        "Invalid notifier ID {:?} in notifiers_for_files_last_seen for file set {:?}",
+                        notifier_id, fileset_id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Start tracking the files followed for a fileset, so files which stay silent after
+    /// startup are still reported. They're treated as last seen when they were last modified.
+    pub(crate) fn add_files<'a>(
+        &mut self,
+        fileset_id: &FileSetId,
+        paths: impl Iterator<Item = &'a Path>,
+    ) {
+        let files = self.seen.entry(fileset_id.clone()).or_default();
+        for path in paths {
+            let modified = fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(DateTime::<Utc>::from);
+            files
+                .entry(path.to_str().expect("Valid string as filename").to_string())
+                .or_insert(SeenFile {
+                    last_seen: modified,
+                    lines: 0,
+                });
+        }
+    }
+
+    pub(crate) fn record(&mut self, fileset_id: FileSetId, file_path: String) {
+        let seen_file = self
+            .seen
+            .entry(fileset_id)
+            .or_default()
+            .entry(file_path)
+            .or_insert(SeenFile {
+                last_seen: None,
+                lines: 0,
+            });
+        seen_file.last_seen = Some(Utc::now());
+        seen_file.lines += 1;
+    }
+
+    /// Files seen for each fileset, sorted by path, optionally only those which are stale
+    pub(crate) fn files(&self, stale_only: bool) -> BTreeMap<FileSetId, Vec<FileSeen>> {
+        let now = Utc::now();
+        self.seen
+            .iter()
+            .map(|(fileset_id, files)| {
+                let mut files = files
+                    .iter()
+                    .map(|(path, seen_file)| {
+                        let seconds_ago = seen_file
+                            .last_seen
+                            .map(|last_seen| (now - last_seen).num_seconds());
+                        FileSeen {
+                            path: path.clone(),
+                            last_seen: seen_file.last_seen,
+                            seconds_ago,
+                            lines: seen_file.lines,
+                            stale: seconds_ago.is_none_or(|seconds_ago| {
+                                self.config
+                                    .stale_after
+                                    .is_some_and(|stale_after| seconds_ago >= stale_after as i64)
+                            }),
+                        }
+                    })
+                    .filter(|file| !stale_only || file.stale)
+                    .collect::<Vec<FileSeen>>();
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+                (fileset_id.clone(), files)
+            })
+            .collect()
+    }
+
+    /// The messages to send, with the notifiers to send each to. Notifiers receive a single
+    /// message covering every fileset they're configured for.
+    pub(crate) fn messages(&self) -> Vec<(Vec<NotifierId>, String)> {
+        let files = self.files(self.config.stale_after.is_some());
+        // Group notifiers by the filesets they receive
+        let mut filesets_for_notifier: BTreeMap<&NotifierId, Vec<&FileSetId>> = BTreeMap::new();
+        for fileset_id in files.keys() {
+            let notifier_ids = self
+                .fileset_notifier_ids
+                .get(fileset_id)
+                .unwrap_or(&self.notifier_ids);
+            for notifier_id in notifier_ids {
+                filesets_for_notifier
+                    .entry(notifier_id)
+                    .or_default()
+                    .push(fileset_id);
+            }
+        }
+        let mut notifiers_for_filesets: BTreeMap<Vec<&FileSetId>, Vec<NotifierId>> =
+            BTreeMap::new();
+        for (notifier_id, fileset_ids) in filesets_for_notifier {
+            notifiers_for_filesets
+                .entry(fileset_ids)
+                .or_default()
+                .push(notifier_id.clone());
+        }
+
+        notifiers_for_filesets
+            .into_iter()
+            .filter_map(|(fileset_ids, notifier_ids)| {
+                let sections = fileset_ids
+                    .iter()
+                    .filter_map(|fileset_id| {
+                        let fileset_files = files.get(*fileset_id)?;
+                        if fileset_files.is_empty() {
+                            return None;
+                        }
+                        Some(format!(
+                            "{}:\n{}",
+                            fileset_id,
+                            fileset_files
+                                .iter()
+                                .map(|file| format!("\t{} : {}\n", file.path, self.when(file)))
+                                .collect::<String>()
+                        ))
+                    })
+                    .collect::<Vec<String>>();
+                // Nothing to say if no files are stale
+                if sections.is_empty() {
+                    return None;
+                }
+                Some((
+                    notifier_ids,
+                    format!("{}\n\n{}\n", self.heading(), sections.join("\n")),
+                ))
+            })
+            .collect()
+    }
+
+    fn heading(&self) -> String {
+        match self.config.stale_after {
+            Some(stale_after) if self.config.humanise => format!(
+                "Files not seen for over {}:",
+                humanise_duration(stale_after as i64)
+            ),
+            Some(stale_after) => format!("Files not seen for over {}s:", stale_after),
+            None => "Files last seen: ".to_string(),
+        }
+    }
+
+    fn when(&self, file: &FileSeen) -> String {
+        let (last_seen, seconds_ago) = match (file.last_seen, file.seconds_ago) {
+            (Some(last_seen), Some(seconds_ago)) => (last_seen, seconds_ago),
+            _ => return "never".to_string(),
+        };
+        if self.config.humanise {
+            format!(
+                "{} ago ({})",
+                humanise_duration(seconds_ago),
+                last_seen
+                    .with_timezone(&self.config.timezone)
+                    .format("%Y-%m-%d %H:%M:%S %Z")
+            )
+        } else {
+            format!("{}s ago", seconds_ago)
+        }
+    }
+}
+
+/// A duration as its two largest units, e.g. "3d 4h" or "5m 12s"
+fn humanise_duration(seconds: i64) -> String {
+    let units = [("d", 60 * 60 * 24), ("h", 60 * 60), ("m", 60), ("s", 1)];
+    let mut remaining = seconds.max(0);
+    let parts = units
+        .iter()
+        .filter_map(|(unit, size)| {
+            let count = remaining / size;
+            remaining %= size;
+            (count > 0).then(|| format!("{}{}", count, unit))
+        })
+        .take(2)
+        .collect::<Vec<String>>();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files_last_seen(stale_after: Option<usize>) -> FilesLastSeen {
+        FilesLastSeen::new(
+            FilesLastSeenConfig {
+                stale_after,
+                ..Default::default()
+            },
+            vec!["chat".to_string()],
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn added_files_are_reported_before_any_lines() {
+        let path = std::env::temp_dir().join(format!("centinela-{}.log", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"").unwrap();
+        let mut files_last_seen = files_last_seen(Some(3600));
+        files_last_seen.add_files(&"app".to_string(), [path.as_path()].into_iter());
+        std::fs::remove_file(&path).unwrap();
+        let files = files_last_seen.files(false);
+        let file = &files["app"][0];
+        assert_eq!(file.path, path.to_str().unwrap());
+        assert_eq!(file.lines, 0);
+        // Seen when it was last modified, which was just now
+        assert!(file.last_seen.is_some());
+        assert!(!file.stale);
+    }
+
+    #[test]
+    fn files_never_seen_are_stale() {
+        let mut files_last_seen = files_last_seen(Some(3600));
+        let missing = std::env::temp_dir().join(format!("centinela-{}.log", uuid::Uuid::new_v4()));
+        files_last_seen.add_files(&"app".to_string(), [missing.as_path()].into_iter());
+        files_last_seen.record("app".to_string(), "/var/log/seen.log".to_string());
+        let stale = files_last_seen.files(true);
+        assert_eq!(stale["app"].len(), 1);
+        assert!(stale["app"][0].last_seen.is_none());
+        let messages = files_last_seen.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].1.contains(" : never"));
+        assert!(!messages[0].1.contains("seen.log"));
+    }
+
+    #[test]
+    fn files_never_seen_are_stale_without_stale_after() {
+        let mut files_last_seen = files_last_seen(None);
+        let missing = std::env::temp_dir().join(format!("centinela-{}.log", uuid::Uuid::new_v4()));
+        files_last_seen.add_files(&"app".to_string(), [missing.as_path()].into_iter());
+        files_last_seen.record("app".to_string(), "/var/log/seen.log".to_string());
+        let stale = files_last_seen.files(true);
+        assert_eq!(stale["app"].len(), 1);
+        assert_eq!(stale["app"][0].path, missing.to_str().unwrap());
+        let files = files_last_seen.files(false);
+        assert_eq!(files["app"].len(), 2);
+        assert!(!files["app"].iter().find(|f| f.lines == 1).unwrap().stale);
+    }
+}
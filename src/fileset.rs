@@ -1,17 +1,18 @@
 use crate::config::FileSetConfig;
 use crate::data::{DataStoreMessage, LogLine};
+use crate::follower::LineFollower;
 use crate::monitor::{Monitor, MonitorId};
 use crate::notifier::NotifierId;
+use crate::redaction::Redactor;
 use core::default::Default;
 use core::option::Option;
 use core::option::Option::{None, Some};
 use core::result::Result;
 use core::result::Result::{Err, Ok};
 use glob::{glob as glob_parser, Paths};
-use linemux::{Line, MuxedLines};
 use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use tokio::sync::mpsc::Sender;
 
@@ -27,6 +28,24 @@ pub(crate) enum LineHandlerMessage {
     Shutdown,
 }
 
+/// A line read from a monitored file, after redaction
+#[derive(Debug, Clone)]
+pub(crate) struct FileLine {
+    source: PathBuf,
+    line: String,
+}
+
+impl FileLine {
+    /// Path of the file the line was read from
+    pub(crate) fn source(&self) -> &Path {
+        self.source.as_path()
+    }
+
+    pub(crate) fn line(&self) -> &str {
+        self.line.as_str()
+    }
+}
+
 /// Struct containing in-memory data about a particular set of monitored files
 pub(crate) struct FileSet {
     pub(crate) config: FileSetConfig,
@@ -40,6 +59,8 @@ pub(crate) struct FileSet {
     /// Calculated value for the maximum number of subsequent lines needed by any of
     /// the active monitors for this FileSet
     pub(crate) max_lines_after: usize,
+    /// Redaction rules applied to each line as it's read
+    pub(crate) redactor: Redactor,
 }
 
 impl FileSet {
@@ -49,27 +70,35 @@ impl FileSet {
         monitors: &HashMap<MonitorId, Monitor>,
     ) -> FileSet {
         let mut set = FileSet {
+            redactor: Redactor::new_from_config(&config.redactions),
             config,
             monitor_notifier_sets: Default::default(),
             line_buffers_before: Default::default(),
             max_lines_before: 0,
             max_lines_after: 0,
         };
-        for (monitor_id, notifier_ids) in &set.config.monitor_notifier_sets {
+        for (monitor_id, notifier_set) in &set.config.monitor_notifier_sets {
             let monitor = monitors
                 .get(monitor_id)
                 .expect("Invalid monitor ID in file_set config.")
                 .clone();
-            set.monitor_notifier_sets
-                .insert(monitor_id.clone(), (monitor, notifier_ids.clone()));
+            set.monitor_notifier_sets.insert(
+                monitor_id.clone(),
+                (
+                    monitor,
+                    notifier_set
+                        .as_ref()
+                        .map(|notifier_set| notifier_set.notifier_ids().clone()),
+                ),
+            );
         }
         set
     }
 
-    /// Create a MuxedLines line follower for this FileSet.
+    /// Create a line follower for this FileSet.
     /// Update self.max_lines_before and self.max_lines_after if necessary.
-    pub(crate) async fn get_follower(&mut self) -> Result<MuxedLines, Box<dyn Error>> {
-        let mut line_follower = match MuxedLines::new() {
+    pub(crate) async fn get_follower(&mut self) -> Result<LineFollower, Box<dyn Error>> {
+        let mut line_follower = match LineFollower::new() {
             Ok(lf) => lf,
             Err(e) => return Err(Box::new(e)),
         };
@@ -136,7 +165,7 @@ impl FileSet {
     pub(crate) async fn line_handler(
         &mut self,
         fileset_id: &FileSetId,
-        mut line_follower: MuxedLines,
+        mut line_follower: LineFollower,
         data_store_tx: Sender<DataStoreMessage>,
         mut line_handler_rx: tokio::sync::mpsc::Receiver<LineHandlerMessage>,
     ) {
@@ -149,7 +178,7 @@ impl FileSet {
                     }
                 }
                 line_res = line_follower.next_line() => {
-                    let line = match line_res {
+                    let raw_line = match line_res {
                         Ok(Some(line)) => line,
                         Ok(None) => {
                             eprintln!("No files added to file set follower: {}", fileset_id);
@@ -160,6 +189,17 @@ impl FileSet {
                             continue;
                         }
                     };
+                    // Scrub secrets before the line is used for anything
+                    let (line, redactions) = self.redactor.redact(raw_line.line());
+                    let line = FileLine {
+                        source: raw_line.source().to_path_buf(),
+                        line,
+                    };
+                    if redactions > 0 {
+                        let _ = data_store_tx
+                            .send(DataStoreMessage::Redacted(fileset_id.to_string(), redactions))
+                            .await;
+                    }
                     // Keep track of when we last received a line from each file
                     let _ = data_store_tx
                         .send(DataStoreMessage::FileSeen(
@@ -203,8 +243,8 @@ impl FileSet {
     }
 
     /// Store a copy of a log line so that it be be used as part of the previous lines for an event
-    fn buffer_line(&mut self, line: &Line) {
-        if self.line_buffers_before.get(line.source()).is_none() {
+    fn buffer_line(&mut self, line: &FileLine) {
+        if !self.line_buffers_before.contains_key(line.source()) {
             self.line_buffers_before
                 .insert(line.source().to_owned(), VecDeque::new());
         }
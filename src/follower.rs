@@ -0,0 +1,322 @@
+use linemux::MuxedEvents;
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use std::collections::{HashMap, VecDeque};
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::{metadata, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+/// A line read from a followed file, before redaction
+#[derive(Debug, Clone)]
+pub(crate) struct RawLine {
+    source: PathBuf,
+    line: String,
+}
+
+impl RawLine {
+    /// Path of the file the line was read from
+    pub(crate) fn source(&self) -> &Path {
+        self.source.as_path()
+    }
+
+    pub(crate) fn line(&self) -> &str {
+        self.line.as_str()
+    }
+}
+
+/// Read position and any incomplete trailing line for a followed file
+#[derive(Default)]
+struct FileState {
+    position: u64,
+    partial: Vec<u8>,
+    /// Identifies the file being read, so a new file at the same path is read from the start
+    id: Option<FileId>,
+}
+
+/// Device and inode numbers of a file, where the platform has them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FileId(u64, u64);
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some(FileId(metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<FileId> {
+    None
+}
+
+/// Follows a set of files for new lines, like linemux's MuxedLines, but reads raw bytes so that
+/// lines which aren't valid UTF-8 are decoded lossily rather than dropped
+pub(crate) struct LineFollower {
+    events: MuxedEvents,
+    files: HashMap<PathBuf, FileState>,
+    lines: VecDeque<RawLine>,
+}
+
+impl LineFollower {
+    pub(crate) fn new() -> io::Result<LineFollower> {
+        Ok(LineFollower {
+            events: MuxedEvents::new()?,
+            files: HashMap::new(),
+            lines: VecDeque::new(),
+        })
+    }
+
+    /// Follow a file from its current end, allowing for files which don't exist yet
+    pub(crate) async fn add_file(&mut self, path: impl Into<PathBuf>) -> io::Result<PathBuf> {
+        let source = self.events.add_file(path).await?;
+        if !self.files.contains_key(&source) && source.exists() {
+            let metadata = metadata(&source).await?;
+            self.files.insert(
+                source.clone(),
+                FileState {
+                    position: metadata.len(),
+                    partial: Vec::new(),
+                    id: file_id(&metadata),
+                },
+            );
+        }
+        Ok(source)
+    }
+
+    /// The followed files which exist
+    pub(crate) fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(|path| path.as_path())
+    }
+
+    /// Wait for the next line from any of the followed files. Returns Ok(None) if no files
+    /// were added.
+    pub(crate) async fn next_line(&mut self) -> io::Result<Option<RawLine>> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(Some(line));
+            }
+            let event = match self.events.next_event().await? {
+                Some(event) => event,
+                None => return Ok(None),
+            };
+            let moved_away = matches!(
+                event.kind,
+                EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From))
+            );
+            if !(moved_away || event.kind.is_create() || event.kind.is_modify()) {
+                continue;
+            }
+            // Read every path before reporting an error, so one file can't hold up another
+            let mut first_error = None;
+            for path in event.paths {
+                if moved_away {
+                    // Rotated or deleted, so anything at the path now is a new file
+                    self.files.remove(&path);
+                }
+                let created = moved_away || event.kind.is_create();
+                if let Err(e) = self.read_new_lines(path, created).await {
+                    first_error.get_or_insert(e);
+                }
+            }
+            if let Some(e) = first_error {
+                return Err(e);
+            }
+        }
+    }
+
+    /// Read anything appended to a file since it was last read. A file which has been
+    /// replaced, e.g. after rotation, is read from the start. A file which has gone is
+    /// forgotten.
+    async fn read_new_lines(&mut self, path: PathBuf, created: bool) -> io::Result<()> {
+        let mut file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.files.remove(&path);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let metadata = file.metadata().await?;
+        let (size, id) = (metadata.len(), file_id(&metadata));
+        let state = match self.files.get_mut(&path) {
+            Some(state) => state,
+            None if created => self.files.entry(path.clone()).or_insert(FileState {
+                id,
+                ..Default::default()
+            }),
+            None => {
+                // Modified without being created, so we've not seen it before. Start at the end.
+                self.files.insert(
+                    path,
+                    FileState {
+                        position: size,
+                        partial: Vec::new(),
+                        id,
+                    },
+                );
+                return Ok(());
+            }
+        };
+        let replaced = match (state.id, id) {
+            (Some(known), Some(id)) => known != id,
+            // Without file IDs, assume a created file is a new one
+            _ => created,
+        };
+        if replaced || size < state.position {
+            // A new file, or truncated
+            *state = FileState {
+                id,
+                ..Default::default()
+            };
+        }
+        if size == state.position {
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(state.position)).await?;
+        let mut data = Vec::new();
+        state.position += file.read_to_end(&mut data).await? as u64;
+        for line in split_lines(&mut state.partial, &data) {
+            self.lines.push_back(RawLine {
+                source: path.clone(),
+                line,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Append data to a partial line and split off any complete lines, decoding them lossily.
+/// Anything after the last newline is left in partial until the rest of the line arrives.
+fn split_lines(partial: &mut Vec<u8>, data: &[u8]) -> Vec<String> {
+    partial.extend_from_slice(data);
+    let complete = match partial.iter().rposition(|byte| *byte == b'\n') {
+        Some(index) => partial.drain(..=index).collect::<Vec<u8>>(),
+        None => return Vec::new(),
+    };
+    // Drop the final newline so it doesn't produce an empty line
+    complete[..complete.len() - 1]
+        .split(|byte| *byte == b'\n')
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            String::from_utf8_lossy(line).into_owned()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines_keeps_partial_line() {
+        let mut partial = Vec::new();
+        assert_eq!(
+            split_lines(&mut partial, b"one\r\ntwo\nthr"),
+            vec!["one".to_string(), "two".to_string()]
+        );
+        assert_eq!(partial, b"thr");
+        assert!(split_lines(&mut partial, b"ee").is_empty());
+        assert_eq!(
+            split_lines(&mut partial, b"\n\n"),
+            vec!["three".to_string(), "".to_string()]
+        );
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn split_lines_replaces_invalid_utf8() {
+        let mut partial = Vec::new();
+        assert_eq!(
+            split_lines(&mut partial, b"ERROR bad \xff byte\n"),
+            vec!["ERROR bad \u{FFFD} byte".to_string()]
+        );
+    }
+
+    /// A path in the temp directory for a test's log file
+    fn temp_log() -> PathBuf {
+        std::env::temp_dir().join(format!("centinela-{}.log", uuid::Uuid::new_v4()))
+    }
+
+    fn append(path: &Path, data: &[u8]) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        std::io::Write::write_all(&mut file, data).unwrap();
+    }
+
+    /// The next line, or None if there isn't one within the timeout
+    async fn next_line(follower: &mut LineFollower, millis: u64) -> Option<RawLine> {
+        tokio::time::timeout(
+            std::time::Duration::from_millis(millis),
+            follower.next_line(),
+        )
+        .await
+        .ok()
+        .map(|line| line.unwrap().unwrap())
+    }
+
+    #[tokio::test]
+    async fn follows_appended_lines_with_invalid_utf8() {
+        let path = temp_log();
+        std::fs::write(&path, b"before\n").unwrap();
+        let mut follower = LineFollower::new().unwrap();
+        let source = follower.add_file(&path).await.unwrap();
+        append(&path, b"ERROR \xff\n");
+        let line = next_line(&mut follower, 5000).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(line.source(), source);
+        assert_eq!(line.line(), "ERROR \u{FFFD}");
+    }
+
+    #[tokio::test]
+    async fn follows_file_after_rotation() {
+        let path = temp_log();
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::write(&path, b"before\n").unwrap();
+        let mut follower = LineFollower::new().unwrap();
+        follower.add_file(&path).await.unwrap();
+        append(&path, b"one\n");
+        assert_eq!(next_line(&mut follower, 5000).await.unwrap().line(), "one");
+        std::fs::rename(&path, &rotated).unwrap();
+        // The rename is handled without an error or any lines
+        assert!(next_line(&mut follower, 300).await.is_none());
+        std::fs::write(&path, b"two\n").unwrap();
+        assert_eq!(next_line(&mut follower, 5000).await.unwrap().line(), "two");
+        append(&path, b"three\n");
+        assert_eq!(
+            next_line(&mut follower, 5000).await.unwrap().line(),
+            "three"
+        );
+        // Nothing is read twice
+        assert!(next_line(&mut follower, 300).await.is_none());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_file_replaced_before_event_from_start() {
+        let path = temp_log();
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::write(&path, b"a long first line before rotation\n").unwrap();
+        let mut follower = LineFollower::new().unwrap();
+        follower.add_file(&path).await.unwrap();
+        // Rotated and replaced by a longer file before the follower sees any events
+        std::fs::rename(&path, &rotated).unwrap();
+        std::fs::write(
+            &path,
+            b"two\nthree\nfour and a much longer line than before\n",
+        )
+        .unwrap();
+        append(Path::new(&rotated), b"late\n");
+        let mut lines = vec![];
+        while let Some(line) = next_line(&mut follower, 1000).await {
+            lines.push(line.line().to_string());
+        }
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+        assert_eq!(
+            lines,
+            vec!["two", "three", "four and a much longer line than before"]
+        );
+    }
+}
@@ -1,23 +1,42 @@
 mod api;
 mod config;
 mod data;
+mod delivery;
+mod escalation;
+mod files_seen;
 mod fileset;
+mod follower;
+mod metrics;
 mod monitor;
 mod notifier;
+mod query;
+mod redaction;
+mod report;
+mod routing;
+mod schedule;
+mod silence;
+mod template;
 
-use crate::config::{ConfigFile, NotifierConfig};
+use crate::config::{ConfigFile, Severity};
 use crate::data::FileSetData;
-use crate::data::{DataStoreMessage, EventCounts, MonitorData};
+use crate::data::{DataStoreMessage, EventCounts, MonitorData, MonitorEvent};
+use crate::delivery::Deliveries;
+use crate::escalation::EscalationPolicies;
+use crate::files_seen::{FilesLastSeen, FilesLastSeenConfig};
 use crate::fileset::{FileSet, FileSetId, LineHandlerMessage};
+use crate::follower::LineFollower;
+use crate::metrics::Queues;
 use crate::monitor::{Monitor, MonitorId};
-use crate::notifier::{Notifier, NotifierId, NotifierMessage, WebhookBackEnd};
+use crate::notifier::{Notifier, NotifierContext, NotifierId, NotifierMessage};
+use crate::report::Reports;
+use crate::routing::Router;
+use crate::silence::{MaintenanceWindows, SilenceTargets};
 use actix_web::{web, App, HttpServer};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use futures::future::{join_all, BoxFuture};
-use linemux::MuxedLines;
 use std::collections::HashMap;
 use std::process::exit;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use structopt::*;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{channel, Sender};
@@ -61,33 +80,144 @@ async fn main() -> Result<(), Error> {
         }
     };
 
-    // Load event counts data from file, if present.
-    let counts: HashMap<FileSetId, HashMap<MonitorId, EventCounts>> =
-        match data::load_data_from_file(&args.data_file) {
-            Ok(data) => {
-                println!("Loaded data file from {}", &args.data_file);
-                data
-            }
-            Err(e) => {
-                eprintln!("Failed to load data from {}: {}", &args.data_file, e);
-                Default::default()
-            }
-        };
+    // Load event counts data, recent events, silences, escalations and open incidents from file,
+    // if present.
+    let persisted_data = match data::load_data_from_file(&args.data_file) {
+        Ok(data) => {
+            println!("Loaded data file from {}", &args.data_file);
+            data
+        }
+        Err(e) => {
+            eprintln!("Failed to load data from {}: {}", &args.data_file, e);
+            Default::default()
+        }
+    };
+    let silences = Arc::new(RwLock_Tokio::new(persisted_data.silences));
 
     // Grab a couple of values before giving away the config object
-    let notifiers_for_files_last_seen = config.global.notifiers_for_files_last_seen.clone();
     let period_for_files_last_seen = config.global.period_for_files_last_seen;
+    let files_last_seen_config = config.global.files_last_seen.clone();
+    let files_last_seen = FilesLastSeen::new(
+        config.global.files_last_seen.clone(),
+        config.global.notifiers_for_files_last_seen.clone(),
+        config
+            .file_sets
+            .iter()
+            .filter_map(|(fileset_id, fileset_config)| {
+                fileset_config
+                    .notifiers_for_files_last_seen
+                    .clone()
+                    .map(|notifier_ids| (fileset_id.clone(), notifier_ids))
+            })
+            .collect(),
+    );
+    let api_url = config.global.api_url.clone();
+    let reports = Reports::new(config.reports.clone());
+    let silence_targets = SilenceTargets {
+        monitors: config
+            .file_sets
+            .iter()
+            .map(|(fileset_id, fileset_config)| {
+                (
+                    fileset_id.clone(),
+                    fileset_config
+                        .monitor_notifier_sets
+                        .keys()
+                        .cloned()
+                        .collect(),
+                )
+            })
+            .collect(),
+        notifiers: config.notifiers.keys().cloned().collect(),
+    };
 
     // Prep structs and data
-    let (mut filesets, filesets_data, _monitors, notifiers) =
-        pop_structs_from_config(config, counts);
-    let files_last_seen_data: HashMap<FileSetId, HashMap<String, DateTime<Utc>>> = HashMap::new();
+    let (
+        mut filesets,
+        filesets_data,
+        monitors,
+        notifiers,
+        maintenance_windows,
+        escalation_policies,
+        router,
+    ) = pop_structs_from_config(config, persisted_data.counts, persisted_data.recent_events);
+    let mut escalations = persisted_data.escalations;
+    escalations.retain_configured(&escalation_policies);
+    let escalations = Arc::new(RwLock_Tokio::new(escalations));
+    let mut open_incidents = persisted_data.open_incidents;
+    open_incidents.retain(|notifier_id, incidents| match notifiers.get(notifier_id) {
+        Some(notifier) => {
+            notifier.back_end.restore_open_incidents(incidents.clone());
+            true
+        }
+        None => {
+            eprintln!(
+                "Dropping {} open incidents for unknown notifier {}",
+                incidents.len(),
+                notifier_id
+            );
+            false
+        }
+    });
+    let open_incidents = Arc::new(RwLock_Tokio::new(open_incidents));
+    reports.validate(&notifiers);
+    files_last_seen.validate(&notifiers);
+    let files_last_seen = Arc::new(RwLock_Tokio::new(files_last_seen));
+
+    // Load notifications awaiting retry from the outbox file, if present.
+    let outbox_file_path = format!("{}.outbox", &args.data_file);
+    let mut deliveries = Deliveries::default();
+    match delivery::load_outbox_from_file(&outbox_file_path) {
+        Ok(outbox) => {
+            deliveries.outbox = outbox
+                .into_iter()
+                .filter(|pending| {
+                    let known = notifiers.contains_key(&pending.notifier_id);
+                    if !known {
+                        eprintln!(
+                            "Dropping queued notification for unknown notifier {}",
+                            pending.notifier_id
+                        );
+                    }
+                    known
+                })
+                .collect();
+            println!(
+                "Loaded {} queued notifications from {}",
+                deliveries.outbox.len(),
+                &outbox_file_path
+            );
+        }
+        Err(e) => eprintln!("Failed to load outbox from {}: {}", &outbox_file_path, e),
+    };
+    let deliveries = Arc::new(RwLock_Tokio::new(deliveries));
 
     // Start long-running tasks
-    let (notifiers_tx, notifier_join_handle) = notifier::start_task(notifiers).await;
+    let (notifiers_tx, notifier_join_handle) = notifier::start_task(
+        notifiers,
+        NotifierContext {
+            maintenance_windows,
+            silences_rwlock: silences.clone(),
+            deliveries_rwlock: deliveries.clone(),
+            outbox_file_path,
+            filesets_data_rwlock: filesets_data.clone(),
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            api_url,
+            monitor_severities: monitor_severities(&monitors),
+            escalation_policies,
+            escalations_rwlock: escalations.clone(),
+            open_incidents_rwlock: open_incidents.clone(),
+        },
+        reports,
+    )
+    .await;
     let (data_store_tx, data_store_join_handle) = data::start_task(
         filesets_data.clone(),
-        files_last_seen_data,
+        files_last_seen.clone(),
+        silences.clone(),
+        escalations.clone(),
+        open_incidents,
+        Arc::new(router),
         notifiers_tx.clone(),
         args.data_file.clone(),
     )
@@ -95,13 +225,40 @@ async fn main() -> Result<(), Error> {
 
     // Start web API
     let wrapped_filesets_data_rwlock = web::Data::new(filesets_data.clone());
+    let wrapped_silences_rwlock = web::Data::new(silences.clone());
+    let wrapped_silence_targets = web::Data::new(silence_targets);
+    let wrapped_deliveries_rwlock = web::Data::new(deliveries.clone());
+    let wrapped_escalations_rwlock = web::Data::new(escalations.clone());
+    let wrapped_files_last_seen_rwlock = web::Data::new(files_last_seen.clone());
+    let wrapped_queues = web::Data::new(Queues {
+        data_store: data_store_tx.clone(),
+        notifier: notifiers_tx.clone(),
+    });
     let actix_future = HttpServer::new(move || {
         App::new()
             .app_data(wrapped_filesets_data_rwlock.clone())
+            .app_data(wrapped_silences_rwlock.clone())
+            .app_data(wrapped_silence_targets.clone())
+            .app_data(wrapped_deliveries_rwlock.clone())
+            .app_data(wrapped_escalations_rwlock.clone())
+            .app_data(wrapped_files_last_seen_rwlock.clone())
+            .app_data(wrapped_queues.clone())
             .service(api::get_filesets)
             .service(api::get_monitors_for_fileset)
             .service(api::get_monitor)
+            .service(api::get_event)
+            .service(api::acknowledge_event)
+            .service(api::add_event_note)
             .service(api::dump)
+            .service(api::get_silences)
+            .service(api::create_silence)
+            .service(api::delete_silence)
+            .service(api::get_deliveries)
+            .service(api::get_escalations)
+            .service(api::acknowledge)
+            .service(api::get_files)
+            .service(api::get_metrics)
+            .service(api::get_counts)
     })
     .bind(("127.0.0.1", 8694))
     .expect("Failed to bind to API port: 8694")
@@ -114,7 +271,7 @@ async fn main() -> Result<(), Error> {
 
     // Timer task to send a summary of which files have been seen and when
     let file_summary_timer_task_join_handle = start_file_summary_timer_task(
-        notifiers_for_files_last_seen,
+        files_last_seen_config,
         period_for_files_last_seen,
         &data_store_tx,
     );
@@ -126,13 +283,17 @@ async fn main() -> Result<(), Error> {
     let mut file_handler_futures: Vec<BoxFuture<()>> = Vec::new();
     let mut file_handler_txs: Vec<Sender<LineHandlerMessage>> = Vec::new();
     for (fileset_id, file_set) in &mut filesets {
-        let line_follower: MuxedLines = match file_set.get_follower().await {
+        let line_follower: LineFollower = match file_set.get_follower().await {
             Ok(lf) => lf,
             Err(e) => {
                 eprintln!("Error: {}", e);
                 exit(1);
             }
         };
+        files_last_seen
+            .write()
+            .await
+            .add_files(fileset_id, line_follower.files());
         let (tx, rx) = channel(32);
         let fut = file_set.line_handler(fileset_id, line_follower, data_store_tx.clone(), rx);
         file_handler_futures.push(Box::pin(fut));
@@ -198,22 +359,31 @@ async fn main() -> Result<(), Error> {
 /// Starts a timer task which periodically sends notifications
 /// indicating which files Centinela is monitoring.
 fn start_file_summary_timer_task(
-    notifiers_for_files_last_seen: Vec<NotifierId>,
+    config: FilesLastSeenConfig,
     period_for_files_last_seen: usize,
     data_store_tx: &Sender<DataStoreMessage>,
 ) -> JoinHandle<()> {
     let data_store_tx_for_timer = data_store_tx.clone();
     tokio::spawn(async move {
-        // Wait before first send
-        sleep(Duration::from_secs(60)).await;
+        // Wait before first send, unless following a schedule
+        if config.cron.is_none() {
+            sleep(Duration::from_secs(config.initial_delay as u64)).await;
+        }
         loop {
+            if let Some(cron) = &config.cron {
+                let next_run = match cron.upcoming(config.timezone).next() {
+                    Some(next_run) => next_run.with_timezone(&Utc),
+                    None => break,
+                };
+                sleep((next_run - Utc::now()).to_std().unwrap_or_default()).await;
+            }
             data_store_tx_for_timer
-                .send(DataStoreMessage::NotifyFilesSeen(
-                    notifiers_for_files_last_seen.clone(),
-                ))
+                .send(DataStoreMessage::NotifyFilesSeen)
                 .await
                 .expect("Datastore task seems to be dead when sending DataStoreMessage::NotifyFilesSeen");
-            sleep(Duration::from_secs(period_for_files_last_seen as u64)).await;
+            if config.cron.is_none() {
+                sleep(Duration::from_secs(period_for_files_last_seen as u64)).await;
+            }
         }
     })
 }
@@ -234,40 +404,76 @@ fn start_persist_data_timer_task(data_store_tx: &Sender<DataStoreMessage>) -> Jo
     })
 }
 
+/// Newtype to simplify the return type of pop_structs_from_config()
+type ConfigStructs = (
+    HashMap<FileSetId, FileSet>,
+    Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>,
+    HashMap<MonitorId, Monitor>,
+    HashMap<NotifierId, Notifier>,
+    MaintenanceWindows,
+    EscalationPolicies,
+    Router,
+);
+
 /// Populates the main in memory data structures based on the config
 /// file and any persisted data in the counts data file
 fn pop_structs_from_config(
     config: ConfigFile,
     counts: HashMap<FileSetId, HashMap<MonitorId, EventCounts>>,
-) -> (
-    HashMap<FileSetId, FileSet>,
-    Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>,
-    HashMap<MonitorId, Monitor>,
-    HashMap<NotifierId, Notifier>,
-) {
+    mut recent_events: HashMap<FileSetId, HashMap<MonitorId, Vec<MonitorEvent>>>,
+) -> ConfigStructs {
+    let mut maintenance_windows = MaintenanceWindows::new(config.maintenance_windows);
+
     let mut monitors: HashMap<MonitorId, Monitor> = Default::default();
     for (monitor_id, monitor_config) in config.monitors {
+        maintenance_windows.attach_to_monitor(&monitor_id, &monitor_config.maintenance_windows);
         monitors.insert(monitor_id.clone(), Monitor::new_from_config(monitor_config));
     }
 
+    let mut escalation_policies = EscalationPolicies::default();
     let mut filesets: HashMap<FileSetId, FileSet> = Default::default();
     let mut filesets_data: HashMap<FileSetId, FileSetData> = Default::default();
 
     for (fileset_id, fileset_conf) in config.file_sets {
+        maintenance_windows.attach_to_fileset(&fileset_id, &fileset_conf.maintenance_windows);
+        for (monitor_id, notifier_set) in &fileset_conf.monitor_notifier_sets {
+            if let Some(notifier_set) = notifier_set {
+                escalation_policies.attach(&fileset_id, monitor_id, notifier_set.escalation());
+            }
+        }
         // Create the FileSet
         let fs = FileSet::new_from_config(fileset_conf, &monitors);
         // Create a FileSetData for the FileSet
         let mut fsd = FileSetData {
             monitor_data: Default::default(),
+            redactions: 0,
         };
         // Create a MonitorData for each Monitor that's used by the FileSet
-        for (monitor_id, (_, _)) in &fs.monitor_notifier_sets {
+        for (monitor_id, (monitor, _)) in &fs.monitor_notifier_sets {
             let mut md = MonitorData::default();
             if let Some(fileset_counts) = counts.get(&fileset_id) {
                 if let Some(monitor_counts) = fileset_counts.get(monitor_id) {
                     md.counts = monitor_counts.clone();
                 }
             }
+            // Restore as many recent events as the monitor keeps. They can't receive any
+            // more lines after a restart.
+            if let Some(events) = recent_events
+                .get_mut(&fileset_id)
+                .and_then(|fileset_events| fileset_events.remove(monitor_id))
+            {
+                let keep = monitor.config.log_recent_events.unwrap_or(0);
+                md.recent_events = events
+                    .into_iter()
+                    .rev()
+                    .take(keep)
+                    .rev()
+                    .map(|mut ev| {
+                        ev.awaiting_lines = 0;
+                        Arc::new(RwLock::new(ev))
+                    })
+                    .collect();
+            }
             fsd.monitor_data.insert(monitor_id.clone(), md);
         }
         filesets.insert(fileset_id.clone(), fs);
@@ -276,20 +482,49 @@ fn pop_structs_from_config(
     let filesets_data_rwlock = Arc::new(RwLock_Tokio::new(filesets_data));
 
     let mut notifiers: HashMap<NotifierId, Notifier> = Default::default();
-    for (notifier_id, notifier_config) in config.notifiers {
+    let notifier_configs = config.notifiers;
+    for (notifier_id, notifier_config) in notifier_configs.clone() {
+        maintenance_windows
+            .attach_to_notifier(&notifier_id, &notifier_config.common().maintenance_windows);
+        let back_end = notifier::new_back_end(&notifier_config, &notifier_configs);
+        let back_end = back_end.unwrap_or_else(|e| {
+            eprintln!("Error in config for notifier {}: {}", notifier_id, e);
+            exit(1);
+        });
         notifiers.insert(
             notifier_id.clone(),
-            match &notifier_config {
-                NotifierConfig::Webhook(wh_config) => Notifier {
-                    config: notifier_config.clone(),
-                    back_end: Box::new(WebhookBackEnd {
-                        config: wh_config.clone(),
-                    }),
-                    last_notify: Utc::now() - chrono::Duration::weeks(52),
-                    skipped_notifications: 0,
-                },
+            Notifier {
+                config: notifier_config,
+                back_end,
+                rate_limits: Default::default(),
+                suppressed: Default::default(),
+                digest: Default::default(),
             },
         );
     }
-    (filesets, filesets_data_rwlock, monitors, notifiers)
+    escalation_policies.validate(&notifiers);
+    let router = Router::new(config.routes, monitor_severities(&monitors));
+    router.validate(&notifiers);
+    (
+        filesets,
+        filesets_data_rwlock,
+        monitors,
+        notifiers,
+        maintenance_windows,
+        escalation_policies,
+        router,
+    )
+}
+
+/// Severities of the monitors which have one
+fn monitor_severities(monitors: &HashMap<MonitorId, Monitor>) -> HashMap<MonitorId, Severity> {
+    monitors
+        .iter()
+        .filter_map(|(monitor_id, monitor)| {
+            monitor
+                .config
+                .severity
+                .map(|severity| (monitor_id.clone(), severity))
+        })
+        .collect()
 }
@@ -0,0 +1,263 @@
+use crate::data::{DataStoreMessage, FileSetData};
+use crate::delivery::Deliveries;
+use crate::escalation::Escalations;
+use crate::files_seen::FilesLastSeen;
+use crate::fileset::FileSetId;
+use crate::notifier::NotifierMessage;
+use crate::silence::Silences;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use tokio::sync::mpsc::Sender;
+
+/// Senders for the internal task queues, so their depth can be reported and the API can
+/// pass messages to the tasks
+#[derive(Clone)]
+pub(crate) struct Queues {
+    pub(crate) data_store: Sender<DataStoreMessage>,
+    pub(crate) notifier: Sender<NotifierMessage>,
+}
+
+impl Queues {
+    /// Messages waiting in each queue
+    fn depths(&self) -> [(&str, usize); 2] {
+        [
+            (
+                "data_store",
+                self.data_store.max_capacity() - self.data_store.capacity(),
+            ),
+            (
+                "notifier",
+                self.notifier.max_capacity() - self.notifier.capacity(),
+            ),
+        ]
+    }
+}
+
+/// A page of metrics in the Prometheus text exposition format
+#[derive(Default)]
+struct Page {
+    text: String,
+}
+
+impl Page {
+    /// Start a metric family with its help text and type
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        self.text += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+    }
+
+    fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        let labels = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+            .collect::<Vec<String>>();
+        match labels.is_empty() {
+            true => self.text += &format!("{} {}\n", name, value),
+            false => self.text += &format!("{}{{{}}} {}\n", name, labels.join(","), value),
+        }
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render the current state of Centinela as Prometheus metrics
+pub(crate) fn render(
+    filesets_data: &HashMap<FileSetId, FileSetData>,
+    files_last_seen: &FilesLastSeen,
+    deliveries: &Deliveries,
+    escalations: &Escalations,
+    silences: &Silences,
+    queues: &Queues,
+) -> String {
+    let mut page = Page::default();
+    let filesets_data = filesets_data.iter().collect::<BTreeMap<_, _>>();
+
+    page.family(
+        "centinela_events_total",
+        "counter",
+        "Lines matched by each monitor on each file set.",
+    );
+    for (fileset_id, fileset_data) in &filesets_data {
+        for (monitor_id, monitor_data) in
+            fileset_data.monitor_data.iter().collect::<BTreeMap<_, _>>()
+        {
+            page.sample(
+                "centinela_events_total",
+                &[("fileset", fileset_id), ("monitor", monitor_id)],
+                monitor_data.counts.total,
+            );
+        }
+    }
+
+    page.family(
+        "centinela_events_unacknowledged",
+        "gauge",
+        "Recent events which haven't been acknowledged.",
+    );
+    for (fileset_id, fileset_data) in &filesets_data {
+        for (monitor_id, monitor_data) in
+            fileset_data.monitor_data.iter().collect::<BTreeMap<_, _>>()
+        {
+            let unacknowledged = monitor_data
+                .recent_events
+                .iter()
+                .filter(|ev| {
+                    ev.read()
+                        .expect("unpoisoned lock")
+                        .acknowledgement
+                        .is_none()
+                })
+                .count();
+            page.sample(
+                "centinela_events_unacknowledged",
+                &[("fileset", fileset_id), ("monitor", monitor_id)],
+                unacknowledged,
+            );
+        }
+    }
+
+    page.family(
+        "centinela_redactions_total",
+        "counter",
+        "Redactions applied to lines read from each file set.",
+    );
+    for (fileset_id, fileset_data) in &filesets_data {
+        page.sample(
+            "centinela_redactions_total",
+            &[("fileset", fileset_id)],
+            fileset_data.redactions,
+        );
+    }
+
+    let files = files_last_seen.files(false);
+    page.family(
+        "centinela_file_lines_total",
+        "counter",
+        "Lines read from each file.",
+    );
+    for (fileset_id, fileset_files) in &files {
+        for file in fileset_files {
+            page.sample(
+                "centinela_file_lines_total",
+                &[("fileset", fileset_id), ("file", &file.path)],
+                file.lines,
+            );
+        }
+    }
+    page.family(
+        "centinela_file_last_seen_timestamp_seconds",
+        "gauge",
+        "When each file last had a new line, as a Unix timestamp.",
+    );
+    for (fileset_id, fileset_files) in &files {
+        // Files which have never been seen have no sample
+        for file in fileset_files {
+            if let Some(last_seen) = file.last_seen {
+                page.sample(
+                    "centinela_file_last_seen_timestamp_seconds",
+                    &[("fileset", fileset_id), ("file", &file.path)],
+                    last_seen.timestamp(),
+                );
+            }
+        }
+    }
+
+    let notifiers = deliveries.notifiers.iter().collect::<BTreeMap<_, _>>();
+    page.family(
+        "centinela_notifications_sent_total",
+        "counter",
+        "Notifications delivered by each notifier.",
+    );
+    for (notifier_id, stats) in &notifiers {
+        page.sample(
+            "centinela_notifications_sent_total",
+            &[("notifier", notifier_id)],
+            stats.sent,
+        );
+    }
+    page.family(
+        "centinela_notification_failures_total",
+        "counter",
+        "Failed delivery attempts by each notifier, including those retried later.",
+    );
+    for (notifier_id, stats) in &notifiers {
+        page.sample(
+            "centinela_notification_failures_total",
+            &[("notifier", notifier_id)],
+            stats.failed_attempts,
+        );
+    }
+    page.family(
+        "centinela_notifications_abandoned_total",
+        "counter",
+        "Notifications given up on by each notifier.",
+    );
+    for (notifier_id, stats) in &notifiers {
+        page.sample(
+            "centinela_notifications_abandoned_total",
+            &[("notifier", notifier_id)],
+            stats.abandoned,
+        );
+    }
+    page.family(
+        "centinela_notifications_skipped_total",
+        "counter",
+        "Event notifications not sent by each notifier, by reason.",
+    );
+    for (notifier_id, stats) in &notifiers {
+        for (reason, skipped) in stats.skipped.iter().collect::<BTreeMap<_, _>>() {
+            page.sample(
+                "centinela_notifications_skipped_total",
+                &[("notifier", notifier_id), ("reason", &reason.to_string())],
+                skipped,
+            );
+        }
+    }
+
+    page.family(
+        "centinela_escalation_steps_notified",
+        "gauge",
+        "Escalation steps notified for each unacknowledged alert.",
+    );
+    for escalation in escalations.open() {
+        page.sample(
+            "centinela_escalation_steps_notified",
+            &[
+                ("fileset", &escalation.fileset_id),
+                ("monitor", &escalation.monitor_id),
+            ],
+            escalation.steps_notified,
+        );
+    }
+    page.family(
+        "centinela_silences_active",
+        "gauge",
+        "Silences which are currently active.",
+    );
+    page.sample("centinela_silences_active", &[], silences.active().len());
+
+    page.family(
+        "centinela_outbox_notifications",
+        "gauge",
+        "Notifications waiting to be retried.",
+    );
+    page.sample(
+        "centinela_outbox_notifications",
+        &[],
+        deliveries.outbox.len(),
+    );
+    page.family(
+        "centinela_queue_depth",
+        "gauge",
+        "Messages waiting in each internal queue.",
+    );
+    for (queue, depth) in queues.depths() {
+        page.sample("centinela_queue_depth", &[("queue", queue)], depth);
+    }
+
+    page.text
+}
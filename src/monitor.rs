@@ -1,8 +1,8 @@
 use crate::config::MonitorConfig;
-use crate::data::{LogLine, MonitorEvent};
+use crate::data::{new_event_id, LogLine, MonitorEvent};
+use crate::fileset::FileLine;
 // use crate::notifier::NotifierId;
-use linemux::Line;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 pub(crate) type MonitorId = String;
 
@@ -19,10 +19,10 @@ impl Monitor {
     /// Process a single logfile line
     pub(crate) async fn handle_line(
         &mut self,
-        line: &Line,
+        line: &FileLine,
         previous_lines: Option<&VecDeque<LogLine>>,
     ) -> Option<MonitorEvent> {
-        if self.config.regex.is_match(line.line()) {
+        if let Some(captures) = self.config.regex.captures(line.line()) {
             // Log line in question
             let log_line = LogLine {
                 date: chrono::offset::Utc::now(),
@@ -51,13 +51,30 @@ impl Monitor {
                 None => vec![log_line],
             };
 
+            // Values of any named capture groups in the monitor's regex
+            let captures = self
+                .config
+                .regex
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|value| (name.to_string(), value.as_str().to_string()))
+                })
+                .collect::<HashMap<String, String>>();
+
             // Create a new match event
             let ev = MonitorEvent {
+                id: new_event_id(),
                 lines,
+                captures,
                 awaiting_lines: self.config.keep_lines_after.unwrap_or(0),
                 awaiting_lines_from: line.source().to_owned(),
                 notify_by: chrono::offset::Utc::now()
                     + chrono::Duration::seconds(self.config.max_wait_before_notify as i64),
+                acknowledgement: None,
+                notes: vec![],
             };
             println!("Generated event for {:#?}", &line);
             // Return
@@ -1,16 +1,55 @@
-use crate::config::{NotifierConfig, WebhookNotifierConfig};
-use crate::data::MonitorEvent;
+use crate::config::{
+    Backoff, HttpNotifierConfig, MessageLimitsConfig, NotifierConfig, OutputFormat, Severity,
+    WebhookNotifierConfig,
+};
+use crate::data::{EventId, FileSetData, LogLine, MonitorEvent, RecentCounts};
+use crate::delivery;
+use crate::delivery::{Deliveries, DeliveryError, SkipReason};
+use crate::escalation::{EscalationPolicies, Escalations};
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::report::Reports;
+use crate::silence::{MaintenanceWindowId, MaintenanceWindows, Silences};
+use crate::template::Template;
 use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::ops::Sub;
+use std::sync::Arc;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::RwLock as RwLock_Tokio;
 use tokio::task::JoinHandle;
 
+mod command;
+mod discord;
+mod email;
+mod failover;
+mod file;
+mod format;
+mod pagerduty;
+mod teams;
+mod telegram;
+
+pub(crate) use command::CommandBackEnd;
+pub(crate) use discord::DiscordBackEnd;
+pub(crate) use email::EmailBackEnd;
+use failover::{FailoverBackEnd, FailoverMember};
+pub(crate) use file::FileBackEnd;
+use format::{DisplayLine, LinesPart};
+pub(crate) use pagerduty::PagerDutyBackEnd;
+pub(crate) use teams::TeamsBackEnd;
+pub(crate) use telegram::TelegramBackEnd;
+
 /// Newtype
 pub(crate) type NotifierId = String;
 
+/// When each incident a back-end has open, by dedup key, last had an event
+pub(crate) type OpenIncidents = HashMap<String, DateTime<Utc>>;
+
 /// Body type for sending webhook messages
 #[derive(Serialize, Deserialize)]
 struct WebhookBody {
@@ -20,8 +59,10 @@ struct WebhookBody {
 /// Messages the notifier task listens for
 #[derive(Debug)]
 pub(crate) enum NotifierMessage {
-    NotifyEvent(Vec<NotifierId>, MonitorEvent),
+    NotifyEvent(Vec<NotifierId>, FileSetId, MonitorId, Box<MonitorEvent>),
     NotifyMessage(Vec<NotifierId>, String),
+    /// The alert for an event was acknowledged through the API. Carries the event's captures.
+    Acknowledge(FileSetId, MonitorId, HashMap<String, String>),
     Shutdown,
 }
 
@@ -29,138 +70,1079 @@ pub(crate) enum NotifierMessage {
 pub(crate) struct Notifier {
     pub(crate) config: NotifierConfig,
     pub(crate) back_end: Box<dyn BackEnd + Sync + Send>,
-    pub(crate) last_notify: DateTime<Utc>,
-    pub(crate) skipped_notifications: usize,
+    /// When notifications were last sent, and how many were skipped, by fileset and monitor
+    pub(crate) rate_limits: HashMap<(FileSetId, MonitorId), RateLimit>,
+    /// Counts of events suppressed by each active maintenance window, by fileset and monitor
+    pub(crate) suppressed: HashMap<MaintenanceWindowId, HashMap<(FileSetId, MonitorId), usize>>,
+    /// Events held back inside minimum_interval, if the notifier is in digest mode
+    pub(crate) digest: Digest,
+}
+
+/// Everything known about an event when a notification is sent. This is the context
+/// available to notification templates.
+#[derive(Serialize)]
+pub(crate) struct EventContext<'a> {
+    pub(crate) fileset_id: &'a FileSetId,
+    pub(crate) monitor_id: &'a MonitorId,
+    /// Path of the file the event line came from
+    pub(crate) source_file: String,
+    pub(crate) hostname: &'a str,
+    /// The monitor's severity, if it has one
+    pub(crate) severity: Option<Severity>,
+    /// Link to the monitor's recent events in the API, if the API URL is configured
+    pub(crate) url: Option<String>,
+    /// ID for fetching, acknowledging or annotating the event through the API
+    pub(crate) event_id: &'a EventId,
+    /// Link to the event in the API, if the API URL is configured and the event is stored
+    pub(crate) event_url: Option<String>,
+    /// All stored lines, including those before and after the event line. Only the lines
+    /// in this part if the event has been split.
+    pub(crate) lines: &'a [LogLine],
+    /// The line which matched the monitor
+    pub(crate) event_line: Option<&'a LogLine>,
+    /// The lines in the notifier's output format, with the event line highlighted,
+    /// sanitised and shortened to fit the notifier's limits
+    pub(crate) lines_text: String,
+    /// The same lines as a markdown code block
+    pub(crate) lines_markdown: String,
+    /// The same lines as plain text
+    pub(crate) lines_plain: String,
+    /// The same lines as preformatted HTML
+    pub(crate) lines_html: String,
+    /// The lines as they should be displayed, shortened to fit the notifier's limits
+    #[serde(skip)]
+    pub(crate) display_lines: Vec<DisplayLine>,
+    /// Which part of the event this is, counting from 1, if it has been split
+    pub(crate) part: usize,
+    pub(crate) parts: usize,
+    /// Values of named capture groups in the monitor's regex
+    pub(crate) captures: &'a HashMap<String, String>,
+    /// Event counts for recent periods
+    pub(crate) counts: RecentCounts,
+    /// Notifications skipped due to minimum_interval since the last one sent
+    pub(crate) skipped: usize,
+    #[serde(skip)]
+    pub(crate) event: &'a MonitorEvent,
+}
+
+impl<'a> EventContext<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        fileset_id: &'a FileSetId,
+        monitor_id: &'a MonitorId,
+        event: &'a MonitorEvent,
+        lines_part: LinesPart<'a>,
+        context: &'a NotifierContext,
+        counts: RecentCounts,
+        skipped: usize,
+        event_url: Option<String>,
+    ) -> EventContext<'a> {
+        EventContext {
+            fileset_id,
+            monitor_id,
+            source_file: event.awaiting_lines_from.to_string_lossy().to_string(),
+            hostname: &context.hostname,
+            severity: context.monitor_severities.get(monitor_id).copied(),
+            url: context.api_url.as_ref().map(|api_url| {
+                format!(
+                    "{}/fileset/{}/monitor/{}",
+                    api_url.trim_end_matches('/'),
+                    fileset_id,
+                    monitor_id
+                )
+            }),
+            event_id: &event.id,
+            event_url,
+            lines: lines_part.lines,
+            event_line: lines_part.lines.iter().find(|line| line.is_event_line),
+            lines_text: format::render(&lines_part.display_lines, lines_part.output_format),
+            lines_markdown: format::markdown_block(&lines_part.display_lines),
+            lines_plain: format::plain_block(&lines_part.display_lines),
+            lines_html: format::html_block(&lines_part.display_lines),
+            display_lines: lines_part.display_lines,
+            part: lines_part.number,
+            parts: lines_part.total,
+            captures: &event.captures,
+            counts,
+            skipped,
+            event,
+        }
+    }
+
+    /// The standard notification text: the lines in the notifier's output format, followed
+    /// by the number of skipped notifications if there were any
+    pub(crate) fn default_text(&self) -> String {
+        let skipped_str = match self.skipped {
+            0 => "".to_string(),
+            _ => format!(
+                "\n\n({} notifications skipped due to high frequency)",
+                self.skipped
+            ),
+        };
+        self.lines_text.clone() + &skipped_str + &self.part_text()
+    }
+
+    /// Note of which part this is, if the event has been split
+    pub(crate) fn part_text(&self) -> String {
+        match self.parts {
+            1 => "".to_string(),
+            _ => format!("\n(part {} of {})", self.part, self.parts),
+        }
+    }
+
+    /// Short description of the event, for titles and subjects
+    pub(crate) fn title(&self) -> String {
+        format!(
+            "{} in {} on {}",
+            self.monitor_id, self.fileset_id, self.hostname
+        )
+    }
+
+    /// Render a template with this context, falling back to `fallback` if rendering fails
+    pub(crate) fn render_or_else<F>(&self, template: &Template, fallback: F) -> String
+    where
+        F: FnOnce(&Self) -> String,
+    {
+        template.render(self).unwrap_or_else(|e| {
+            println!("Failed to render notification template: {}", e);
+            fallback(self)
+        })
+    }
 }
 
-/// Trait to be implemented by Notifier back-ends.
+/// Shorten text to at most max_chars characters, marking where it was cut and closing
+/// any markdown code block left open
+pub(crate) fn truncate_chars(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    let mut truncated = text
+        .chars()
+        .take(max_chars.saturating_sub(6))
+        .collect::<String>()
+        + "\n…";
+    if truncated.matches("```").count() % 2 == 1 {
+        truncated += "\n```";
+    }
+    truncated
+}
+
+/// Rate limiting state for one fileset and monitor on a notifier
+#[derive(Default)]
+pub(crate) struct RateLimit {
+    last_notify: Option<DateTime<Utc>>,
+    last_event: Option<DateTime<Utc>>,
+    /// Events in the current burst, for exponential backoff
+    burst: usize,
+    /// Notifications skipped since the last one sent
+    skipped: usize,
+}
+
+impl RateLimit {
+    /// Record an event, returning whether a notification should be sent for it
+    fn allow(&mut self, minimum_interval: Duration, backoff: Backoff) -> bool {
+        let now = Utc::now();
+        let allowed = match backoff {
+            Backoff::Interval => self
+                .last_notify
+                .is_none_or(|last_notify| now.sub(minimum_interval) > last_notify),
+            Backoff::Exponential => {
+                if self
+                    .last_event
+                    .is_none_or(|last_event| now.sub(minimum_interval) > last_event)
+                {
+                    self.burst = 0;
+                }
+                self.burst += 1;
+                is_power_of_ten(self.burst)
+            }
+        };
+        self.last_event = Some(now);
+        allowed
+    }
+
+    /// Record a notification being sent, returning the number skipped before it
+    fn sent(&mut self) -> usize {
+        self.last_notify = Some(Utc::now());
+        std::mem::take(&mut self.skipped)
+    }
+}
+
+fn is_power_of_ten(mut n: usize) -> bool {
+    while n >= 10 && n.is_multiple_of(10) {
+        n /= 10;
+    }
+    n == 1
+}
+
+/// Events held back for a digest notification
+#[derive(Default)]
+pub(crate) struct Digest {
+    since: Option<DateTime<Utc>>,
+    entries: HashMap<(FileSetId, MonitorId), DigestEntry>,
+}
+
+/// Held back events for a single fileset and monitor
+#[derive(Default)]
+struct DigestEntry {
+    count: usize,
+    examples: Vec<String>,
+}
+
+impl Digest {
+    /// Hold back an event, keeping up to max_examples of its event lines
+    fn add(
+        &mut self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        ev: &MonitorEvent,
+        max_examples: usize,
+    ) {
+        self.since.get_or_insert_with(Utc::now);
+        let entry = self
+            .entries
+            .entry((fileset_id.clone(), monitor_id.clone()))
+            .or_default();
+        entry.count += 1;
+        if entry.examples.len() < max_examples {
+            if let Some(line) = ev.lines.iter().find(|line| line.is_event_line) {
+                entry.examples.push(line.to_string());
+            }
+        }
+    }
+
+    /// Build the digest message and reset. Returns None if nothing was held back.
+    fn take_message(&mut self) -> Option<String> {
+        let since = self.since.take()?;
+        let mut entries = self.entries.drain().collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let total: usize = entries.iter().map(|(_, entry)| entry.count).sum();
+        let groups = entries
+            .iter()
+            .map(|((fileset_id, monitor_id), entry)| {
+                format!(
+                    "{} / {} : {}\n{}",
+                    fileset_id,
+                    monitor_id,
+                    entry.count,
+                    entry
+                        .examples
+                        .iter()
+                        .fold(String::new(), |acc, line| acc + "\t" + line + "\n")
+                )
+            })
+            .collect::<Vec<String>>();
+        Some(format!(
+            "Digest of {} notifications since {}:\n\n{}",
+            total,
+            since,
+            groups.join("\n")
+        ))
+    }
+}
+
+/// Trait to be implemented by Notifier back-ends. Back-ends turn events and messages into
+/// payloads, which are delivered separately so that failed deliveries can be retried.
 #[async_trait]
 pub(crate) trait BackEnd {
-    async fn notify_event(&self, ev: &MonitorEvent, skipped_notifications: usize);
-    async fn notify_message(&self, message: &str);
+    fn event_payload(&self, event_context: &EventContext) -> String;
+    fn message_payload(&self, message: &str) -> String;
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError>;
+
+    /// Limits used for any not set in the notifier's config
+    fn default_limits(&self) -> MessageLimitsConfig {
+        Default::default()
+    }
+
+    /// Output format used if not set in the notifier's config
+    fn default_format(&self) -> OutputFormat {
+        OutputFormat::Markdown
+    }
+
+    /// Payload for a scheduled report. `text` is markdown, and `html` is given if the report
+    /// should be sent as HTML where the back-end supports it.
+    fn report_payload(&self, _title: &str, text: &str, _html: Option<&str>) -> String {
+        self.message_payload(text)
+    }
+
+    /// Called for every event for the notifier, whether or not it's sent
+    fn event_seen(
+        &self,
+        _fileset_id: &FileSetId,
+        _monitor_id: &MonitorId,
+        _captures: &HashMap<String, String>,
+    ) {
+    }
+
+    /// Payloads the back-end wants to send on its own account, e.g. to resolve incidents
+    /// which have cleared. Called periodically by the notifier task.
+    fn due_payloads(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Payloads to send when the alert for an event is acknowledged through the API, e.g. to
+    /// acknowledge an incident
+    fn acknowledge_payloads(
+        &self,
+        _fileset_id: &FileSetId,
+        _monitor_id: &MonitorId,
+        _captures: &HashMap<String, String>,
+    ) -> Vec<String> {
+        vec![]
+    }
+
+    /// Incidents the back-end has open, which are saved in the data file
+    fn open_incidents(&self) -> OpenIncidents {
+        Default::default()
+    }
+
+    /// Restore open incidents loaded from the data file
+    fn restore_open_incidents(&self, _open_incidents: OpenIncidents) {}
 }
 
 /// Slack/Mattermost webhook
 pub struct WebhookBackEnd {
     pub(crate) config: WebhookNotifierConfig,
+    pub(crate) client: reqwest::Client,
+}
+
+impl WebhookBackEnd {
+    pub(crate) fn new(config: WebhookNotifierConfig) -> WebhookBackEnd {
+        WebhookBackEnd {
+            config,
+            client: delivery::http_client(),
+        }
+    }
+
+    /// An event as a Slack/Mattermost attachment, coloured by severity
+    fn attachment_payload(&self, event_context: &EventContext) -> String {
+        let colour = match event_context.severity {
+            Some(Severity::Critical) => "#a30200",
+            Some(Severity::Error) => "#e01e5a",
+            Some(Severity::Warning) => "#daa038",
+            Some(Severity::Info) => "#439fe0",
+            None => "#808080",
+        };
+        let text = match &self.config.body_template {
+            Some(body_template) => event_context
+                .render_or_else(body_template, |context| context.lines_markdown.clone()),
+            None => event_context.lines_markdown.clone(),
+        };
+        let mut fields = vec![
+            json!({ "title": "File set", "value": event_context.fileset_id, "short": true }),
+            json!({ "title": "Monitor", "value": event_context.monitor_id, "short": true }),
+            json!({ "title": "Hostname", "value": event_context.hostname, "short": true }),
+            json!({ "title": "Last hour", "value": event_context.counts.last_hour.to_string(), "short": true }),
+            json!({ "title": "Source file", "value": event_context.source_file, "short": false }),
+        ];
+        if event_context.skipped > 0 {
+            fields.push(json!({ "title": "Skipped", "value": event_context.skipped.to_string(), "short": true }));
+        }
+        if event_context.parts > 1 {
+            fields.push(json!({ "title": "Part", "value": format!("{} of {}", event_context.part, event_context.parts), "short": true }));
+        }
+        let mut attachment = json!({
+            "fallback": event_context.title(),
+            "color": colour,
+            "title": event_context.title(),
+            "text": text,
+            "fields": fields,
+            "footer": "Centinela",
+            "ts": event_context
+                .event_line
+                .map(|line| line.date)
+                .unwrap_or_else(Utc::now)
+                .timestamp(),
+        });
+        if let Some(url) = &event_context.url {
+            attachment["title_link"] = json!(url);
+        }
+        json!({
+            "text": self.config.template,
+            "attachments": [attachment],
+        })
+        .to_string()
+    }
 }
 
 #[async_trait]
 impl BackEnd for WebhookBackEnd {
-    async fn notify_event(&self, ev: &MonitorEvent, skipped_notifications: usize) {
-        let client = reqwest::Client::new();
-        let skipped_str = match skipped_notifications {
-            0 => "".to_string(),
-            _ => format!(
-                "\n\n({} notifications skipped due to high frequency)",
-                skipped_notifications
-            ),
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        if self.config.attachments {
+            return self.attachment_payload(event_context);
+        }
+        let text = match &self.config.body_template {
+            Some(body_template) => {
+                event_context.render_or_else(body_template, EventContext::default_text)
+            }
+            None => self.config.template.to_owned() + &event_context.default_text(),
         };
+        let body = WebhookBody { text };
+        serde_json::to_string(&body).expect("Failed to build JSON")
+    }
+
+    fn message_payload(&self, message: &str) -> String {
         let body = WebhookBody {
-            text: self.config.template.to_owned()
-                + ev.get_lines_as_markdown().as_str()
-                + &skipped_str,
+            text: message.to_owned(),
         };
-        let res = client
+        serde_json::to_string(&body).expect("Failed to build JSON")
+    }
+
+    /// Mattermost rejects posts over 16383 characters, and Slack truncates long messages
+    fn default_limits(&self) -> MessageLimitsConfig {
+        MessageLimitsConfig {
+            max_chars: Some(15000),
+            max_line_chars: Some(2000),
+            split: false,
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let res = self
+            .client
             .post(self.config.url.as_str())
-            .body(serde_json::to_string(&body).expect("Failed to build JSON"))
+            .body(payload.to_owned())
             .send()
             .await;
-        match res {
-            Ok(_res) => {
-                println!("Sent event notification");
-            }
-            Err(e) => {
-                println!("Failed to send event notification: {:?}", e);
-            }
-        };
+        delivery::check_response(res)
     }
+}
 
-    async fn notify_message(&self, message: &str) {
-        let client = reqwest::Client::new();
-        let body = WebhookBody {
-            text: message.to_owned(),
+/// Generic HTTP notifier with a templated body
+pub struct HttpBackEnd {
+    pub(crate) config: HttpNotifierConfig,
+    pub(crate) client: reqwest::Client,
+    pub(crate) method: Method,
+    pub(crate) headers: HeaderMap,
+}
+
+impl HttpBackEnd {
+    /// Create the back-end, resolving header values from the environment or files
+    pub(crate) fn new(config: HttpNotifierConfig) -> Result<HttpBackEnd, String> {
+        let method = match &config.method {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|e| format!("invalid method {}: {}", method, e))?,
+            None => Method::POST,
         };
-        let res = client
-            .post(self.config.url.as_str())
-            .body(serde_json::to_string(&body).expect("Failed to build JSON"))
+        let mut headers = HeaderMap::new();
+        let content_type = config.content_type.as_deref().unwrap_or("application/json");
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(content_type)
+                .map_err(|e| format!("invalid content type {}: {}", content_type, e))?,
+        );
+        for (name, value) in config.headers.iter().flatten() {
+            let value = value
+                .resolve()
+                .map_err(|e| format!("header {}: {}", name, e))?;
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("invalid header name {}: {}", name, e))?,
+                HeaderValue::from_str(&value)
+                    .map_err(|e| format!("invalid value for header {}: {}", name, e))?,
+            );
+        }
+        Ok(HttpBackEnd {
+            config,
+            client: delivery::http_client(),
+            method,
+            headers,
+        })
+    }
+}
+
+#[async_trait]
+impl BackEnd for HttpBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        match &self.config.body_template {
+            Some(body_template) => event_context.render_or_else(body_template, |context| {
+                serde_json::to_string(context).expect("Failed to build JSON")
+            }),
+            None => serde_json::to_string(event_context).expect("Failed to build JSON"),
+        }
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        let context = json!({ "message": message });
+        match &self.config.message_template {
+            Some(message_template) => message_template.render(&context).unwrap_or_else(|e| {
+                println!("Failed to render message template: {}", e);
+                message.to_owned()
+            }),
+            None => json!({ "text": message }).to_string(),
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let res = self
+            .client
+            .request(self.method.clone(), self.config.url.as_str())
+            .headers(self.headers.clone())
+            .body(payload.to_owned())
             .send()
             .await;
-        match res {
-            Ok(_res) => {
-                println!("Sent message notification");
+        delivery::check_response(res)
+    }
+}
+
+/// Create the back-end for a notifier. Failover notifiers get their own copies of their
+/// members' back-ends.
+pub(crate) fn new_back_end(
+    notifier_config: &NotifierConfig,
+    notifier_configs: &HashMap<NotifierId, NotifierConfig>,
+) -> Result<Box<dyn BackEnd + Sync + Send>, String> {
+    let common = notifier_config.common();
+    if common.backoff == Backoff::Exponential && common.minimum_interval.is_none() {
+        return Err("exponential backoff requires minimum_interval".to_string());
+    }
+    Ok(match notifier_config {
+        NotifierConfig::Webhook(conf) => Box::new(WebhookBackEnd::new(conf.clone())),
+        NotifierConfig::Http(conf) => Box::new(HttpBackEnd::new(conf.clone())?),
+        NotifierConfig::Email(conf) => Box::new(EmailBackEnd::new(conf.clone())?),
+        NotifierConfig::Command(conf) => Box::new(CommandBackEnd::new(conf.clone())),
+        NotifierConfig::File(conf) => Box::new(FileBackEnd::new(conf.clone())),
+        NotifierConfig::PagerDuty(conf) => Box::new(PagerDutyBackEnd::new(conf.clone())?),
+        NotifierConfig::Teams(conf) => Box::new(TeamsBackEnd::new(conf.clone())),
+        NotifierConfig::Discord(conf) => Box::new(DiscordBackEnd::new(conf.clone())),
+        NotifierConfig::Telegram(conf) => Box::new(TelegramBackEnd::new(conf.clone())?),
+        NotifierConfig::Failover(conf) => {
+            let mut members = vec![];
+            for notifier_id in &conf.notifiers {
+                let member_config = notifier_configs
+                    .get(notifier_id)
+                    .ok_or_else(|| format!("unknown notifier {}", notifier_id))?;
+                if let NotifierConfig::Failover(_) = member_config {
+                    return Err(format!("{} is also a failover notifier", notifier_id));
+                }
+                members.push(FailoverMember {
+                    notifier_id: notifier_id.clone(),
+                    config: member_config.clone(),
+                    back_end: new_back_end(member_config, notifier_configs)?,
+                });
             }
-            Err(e) => {
-                println!("Failed to send message notification {:?}", e);
+            Box::new(FailoverBackEnd::new(conf.clone(), members))
+        }
+    })
+}
+
+/// Send an event notification if and when appropriate. Returns whether it was sent, or
+/// queued for retry.
+pub(crate) async fn notify_event(
+    notifier: &mut Notifier,
+    notifier_id: &NotifierId,
+    fileset_id: &FileSetId,
+    monitor_id: &MonitorId,
+    ev_clone: &MonitorEvent,
+    context: &NotifierContext,
+) -> bool {
+    notifier
+        .back_end
+        .event_seen(fileset_id, monitor_id, &ev_clone.captures);
+    // Runtime silences created via the API
+    if let Some(silence) = context.silences_rwlock.read().await.matching_event(
+        fileset_id,
+        monitor_id,
+        notifier_id,
+        &ev_clone.captures,
+    ) {
+        println!(
+            "Notification for {} / {} via {} silenced by {}",
+            fileset_id, monitor_id, notifier_id, silence.id
+        );
+        context
+            .deliveries_rwlock
+            .write()
+            .await
+            .record_skipped(notifier_id, SkipReason::Silenced);
+        return false;
+    }
+    // Hold back notifications during maintenance windows, keeping track of what was suppressed
+    if let Some(window_id) =
+        context
+            .maintenance_windows
+            .active_window(fileset_id, monitor_id, notifier_id)
+    {
+        *notifier
+            .suppressed
+            .entry(window_id.clone())
+            .or_default()
+            .entry((fileset_id.clone(), monitor_id.clone()))
+            .or_default() += 1;
+        context
+            .deliveries_rwlock
+            .write()
+            .await
+            .record_skipped(notifier_id, SkipReason::MaintenanceWindow);
+        return false;
+    }
+    // Limit how often notifications are sent for each fileset and monitor
+    if skip_if_rate_limited(notifier, fileset_id, monitor_id, ev_clone) {
+        //println!("Skipping notify due to frequency");
+        context
+            .deliveries_rwlock
+            .write()
+            .await
+            .record_skipped(notifier_id, SkipReason::RateLimited);
+        return false;
+    }
+    // Send anything held back before the new event
+    if let Some(message) = notifier.digest.take_message() {
+        notify_message(notifier, notifier_id, &message, context).await;
+    }
+    let num_skipped = notifier
+        .rate_limits
+        .entry((fileset_id.clone(), monitor_id.clone()))
+        .or_default()
+        .sent();
+    // Send notification, in several parts if it's too long and the notifier splits events
+    let counts = context.recent_counts(fileset_id, monitor_id).await;
+    let event_url = context
+        .event_url(fileset_id, monitor_id, &ev_clone.id)
+        .await;
+    let limits = notifier
+        .config
+        .common()
+        .limits
+        .or(notifier.back_end.default_limits());
+    let output_format = notifier
+        .config
+        .common()
+        .format
+        .unwrap_or_else(|| notifier.back_end.default_format());
+    for lines_part in format::layout(&ev_clone.lines, &limits, output_format) {
+        let event_context = EventContext::new(
+            fileset_id,
+            monitor_id,
+            ev_clone,
+            lines_part,
+            context,
+            counts.clone(),
+            num_skipped,
+            event_url.clone(),
+        );
+        let payload = notifier.back_end.event_payload(&event_context);
+        send(notifier, notifier_id, payload, context).await;
+    }
+    true
+}
+
+/// Send a plain message
+pub(crate) async fn notify_message(
+    notifier: &Notifier,
+    notifier_id: &NotifierId,
+    message: &str,
+    context: &NotifierContext,
+) {
+    let payload = notifier.back_end.message_payload(message);
+    send(notifier, notifier_id, payload, context).await;
+}
+
+/// Deliver a payload, queueing it in the outbox for retry if delivery fails
+async fn send(
+    notifier: &Notifier,
+    notifier_id: &NotifierId,
+    payload: String,
+    context: &NotifierContext,
+) {
+    let res = notifier.back_end.deliver(&payload).await;
+    let mut deliveries = context.deliveries_rwlock.write().await;
+    match res {
+        Ok(()) => {
+            println!("Sent notification via {}", notifier_id);
+            deliveries.record_success(notifier_id);
+        }
+        Err(error) => {
+            println!("Failed to send notification via {}: {}", notifier_id, error);
+            deliveries.record_failure(notifier_id, &error);
+            let retry = &notifier.config.common().retry;
+            if error.permanent || retry.max_attempts <= 1 {
+                deliveries.record_abandoned(notifier_id);
+            } else {
+                let next_attempt = retry.next_attempt(1, error.retry_after);
+                deliveries.enqueue(notifier_id, payload, &error, next_attempt);
+                delivery::persist_outbox(&deliveries.outbox, &context.outbox_file_path);
+            }
+        }
+    }
+}
+
+/// Retry queued deliveries which are due, a limited number at a time. Once a notifier fails,
+/// its other deliveries wait for the next tick so an unreachable receiver only costs one
+/// timeout per tick.
+async fn retry_pending_deliveries(
+    notifiers: &HashMap<NotifierId, Notifier>,
+    context: &NotifierContext,
+) {
+    let due = context
+        .deliveries_rwlock
+        .write()
+        .await
+        .take_due(delivery::MAX_RETRIES_PER_TICK);
+    if due.is_empty() {
+        return;
+    }
+    let mut failed = HashSet::new();
+    for mut pending in due {
+        let notifier = match notifiers.get(&pending.notifier_id) {
+            Some(notifier) => notifier,
+            None => {
+                println!(
+                    "Dropping queued notification for unknown notifier {}",
+                    pending.notifier_id
+                );
+                continue;
             }
         };
+        if failed.contains(&pending.notifier_id) {
+            context.deliveries_rwlock.write().await.outbox.push(pending);
+            continue;
+        }
+        let res = notifier.back_end.deliver(&pending.payload).await;
+        let mut deliveries = context.deliveries_rwlock.write().await;
+        match res {
+            Ok(()) => {
+                println!(
+                    "Sent queued notification via {} after {} attempts",
+                    pending.notifier_id,
+                    pending.attempts + 1
+                );
+                deliveries.record_success(&pending.notifier_id);
+            }
+            Err(error) => {
+                deliveries.record_failure(&pending.notifier_id, &error);
+                failed.insert(pending.notifier_id.clone());
+                pending.attempts += 1;
+                let retry = &notifier.config.common().retry;
+                if error.permanent || pending.attempts >= retry.max_attempts {
+                    println!(
+                        "Giving up on notification via {} after {} attempts: {}",
+                        pending.notifier_id, pending.attempts, error
+                    );
+                    deliveries.record_abandoned(&pending.notifier_id);
+                } else {
+                    pending.next_attempt = retry.next_attempt(pending.attempts, error.retry_after);
+                    pending.last_error = error.message;
+                    deliveries.outbox.push(pending);
+                }
+            }
+        }
+    }
+    let deliveries = context.deliveries_rwlock.read().await;
+    delivery::persist_outbox(&deliveries.outbox, &context.outbox_file_path);
+}
+
+/// Send a summary of suppressed events for any maintenance windows which have ended
+async fn notify_ended_maintenance_windows(
+    notifier: &mut Notifier,
+    notifier_id: &NotifierId,
+    context: &NotifierContext,
+) {
+    let ended = notifier
+        .suppressed
+        .keys()
+        .filter(|window_id| !context.maintenance_windows.is_active(window_id))
+        .cloned()
+        .collect::<Vec<MaintenanceWindowId>>();
+    for window_id in ended {
+        let suppressed = notifier
+            .suppressed
+            .remove(&window_id)
+            .expect("Suppressed events for ended maintenance window");
+        let mut lines = suppressed
+            .iter()
+            .map(|((fileset_id, monitor_id), count)| {
+                format!("\t{} / {} : {}", fileset_id, monitor_id, count)
+            })
+            .collect::<Vec<String>>();
+        lines.sort();
+        let message = format!(
+            "Maintenance window {} ended. Suppressed notifications:\n\n{}\n",
+            window_id,
+            lines.join("\n")
+        );
+        notify_message(notifier, notifier_id, &message, context).await;
     }
 }
 
-/// Send an event notification if and when appropriate
-pub(crate) async fn notify_event(mut notifier: &mut Notifier, ev_clone: &MonitorEvent) {
-    // Limit how often notifications are sent
-    let mininum_interval = match &notifier.config {
-        NotifierConfig::Webhook(conf) => conf.minimum_interval,
+/// Send anything the back-end has scheduled itself
+async fn notify_due_payloads(
+    notifier: &Notifier,
+    notifier_id: &NotifierId,
+    context: &NotifierContext,
+) {
+    for payload in notifier.back_end.due_payloads() {
+        send(notifier, notifier_id, payload, context).await;
+    }
+}
+
+/// Copy the notifiers' open incidents to where the data store can save them
+async fn sync_open_incidents(notifiers: &HashMap<NotifierId, Notifier>, context: &NotifierContext) {
+    let mut open_incidents = context.open_incidents_rwlock.write().await;
+    for (notifier_id, notifier) in notifiers {
+        let incidents = notifier.back_end.open_incidents();
+        if incidents.is_empty() {
+            open_incidents.remove(notifier_id);
+        } else {
+            open_incidents.insert(notifier_id.clone(), incidents);
+        }
+    }
+}
+
+/// Notify the next step of any escalations which have gone unacknowledged for long enough
+async fn notify_due_escalations(
+    notifiers: &mut HashMap<NotifierId, Notifier>,
+    context: &NotifierContext,
+) {
+    let due = context
+        .escalations_rwlock
+        .write()
+        .await
+        .take_due_steps(&context.escalation_policies);
+    for (notifier_ids, escalation) in due {
+        println!(
+            "Escalating {} / {} to step {}",
+            escalation.fileset_id, escalation.monitor_id, escalation.steps_notified
+        );
+        for notifier_id in &notifier_ids {
+            notify_event(
+                notifiers
+                    .get_mut(notifier_id)
+                    .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id)),
+                notifier_id,
+                &escalation.fileset_id,
+                &escalation.monitor_id,
+                &escalation.event,
+                context,
+            )
+            .await;
+        }
+    }
+}
+
+/// Send any scheduled reports which are due
+async fn notify_due_reports(
+    notifiers: &HashMap<NotifierId, Notifier>,
+    reports: &mut Reports,
+    context: &NotifierContext,
+) {
+    let due = {
+        let filesets_data = context.filesets_data_rwlock.read().await;
+        reports.take_due(&filesets_data, &context.hostname)
     };
-    if skip_if_inside_minimum_interval(notifier, mininum_interval) {
-        //println!("Skipping notify due to frequency");
-        return;
+    for (notifier_ids, report) in due {
+        for notifier_id in &notifier_ids {
+            if let Some(silence) = context
+                .silences_rwlock
+                .read()
+                .await
+                .matching_message(notifier_id)
+            {
+                println!("Report via {} silenced by {}", notifier_id, silence.id);
+                continue;
+            }
+            let notifier = notifiers
+                .get(notifier_id)
+                .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id));
+            let payload = notifier.back_end.report_payload(
+                &report.title,
+                &report.text,
+                report.html.as_deref(),
+            );
+            send(notifier, notifier_id, payload, context).await;
+        }
     }
-    let num_skipped = notifier.skipped_notifications;
-    notifier.skipped_notifications = 0;
-    notifier.last_notify = Utc::now();
-    // Send notification
-    notifier.back_end.notify_event(ev_clone, num_skipped).await;
 }
 
-/// Check whether the minimum interval between notifications has elapsed
-fn skip_if_inside_minimum_interval(
-    mut notifier: &mut Notifier,
-    minimum_interval_option: Option<usize>,
-) -> bool {
-    if let Some(minimum_interval) = minimum_interval_option {
-        let now = Utc::now();
-        if now.sub(Duration::seconds(minimum_interval as i64)) <= notifier.last_notify {
-            notifier.skipped_notifications += 1;
-            return true;
+/// Send a digest of held back events once the minimum interval has elapsed since the
+/// first of them
+async fn notify_digest_if_due(
+    notifier: &mut Notifier,
+    notifier_id: &NotifierId,
+    context: &NotifierContext,
+) {
+    let since = match notifier.digest.since {
+        Some(since) => since,
+        None => return,
+    };
+    if let Some(minimum_interval) = notifier.config.common().minimum_interval {
+        if Utc::now().sub(Duration::seconds(minimum_interval as i64)) <= since {
+            return;
         }
+    }
+    if let Some(message) = notifier.digest.take_message() {
+        notify_message(notifier, notifier_id, &message, context).await;
+    }
+}
+
+/// Check whether a notification is allowed for the fileset and monitor, according to the
+/// notifier's minimum interval and backoff. If not, the event is either skipped or held
+/// back for a digest.
+fn skip_if_rate_limited(
+    notifier: &mut Notifier,
+    fileset_id: &FileSetId,
+    monitor_id: &MonitorId,
+    ev: &MonitorEvent,
+) -> bool {
+    let common = notifier.config.common();
+    let minimum_interval = match common.minimum_interval {
+        Some(minimum_interval) => Duration::seconds(minimum_interval as i64),
+        None => return false,
     };
-    false
+    let rate_limit = notifier
+        .rate_limits
+        .entry((fileset_id.clone(), monitor_id.clone()))
+        .or_default();
+    if rate_limit.allow(minimum_interval, common.backoff) {
+        return false;
+    }
+    match &common.digest {
+        Some(digest_config) => {
+            notifier
+                .digest
+                .add(fileset_id, monitor_id, ev, digest_config.examples)
+        }
+        None => rate_limit.skipped += 1,
+    }
+    true
+}
+
+/// State used by the notifier task in addition to the notifiers themselves
+pub(crate) struct NotifierContext {
+    pub(crate) maintenance_windows: MaintenanceWindows,
+    pub(crate) silences_rwlock: Arc<RwLock_Tokio<Silences>>,
+    pub(crate) deliveries_rwlock: Arc<RwLock_Tokio<Deliveries>>,
+    pub(crate) outbox_file_path: String,
+    pub(crate) filesets_data_rwlock: Arc<RwLock_Tokio<HashMap<FileSetId, FileSetData>>>,
+    pub(crate) hostname: String,
+    pub(crate) api_url: Option<String>,
+    pub(crate) monitor_severities: HashMap<MonitorId, Severity>,
+    pub(crate) escalation_policies: EscalationPolicies,
+    pub(crate) escalations_rwlock: Arc<RwLock_Tokio<Escalations>>,
+    /// Copy of the notifiers' open incidents, for saving in the data file
+    pub(crate) open_incidents_rwlock: Arc<RwLock_Tokio<HashMap<NotifierId, OpenIncidents>>>,
+}
+
+impl NotifierContext {
+    /// Link to an event in the API. None if the API URL isn't configured, or the event isn't
+    /// stored because the monitor doesn't keep recent events or it's already been trimmed.
+    async fn event_url(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        event_id: &EventId,
+    ) -> Option<String> {
+        let api_url = self.api_url.as_ref()?;
+        let stored = self
+            .filesets_data_rwlock
+            .read()
+            .await
+            .get(fileset_id)
+            .and_then(|fileset_data| fileset_data.monitor_data.get(monitor_id))
+            .is_some_and(|monitor_data| monitor_data.event(event_id).is_some());
+        stored.then(|| {
+            format!(
+                "{}/fileset/{}/monitor/{}/event/{}",
+                api_url.trim_end_matches('/'),
+                fileset_id,
+                monitor_id,
+                event_id
+            )
+        })
+    }
+
+    /// Event counts for recent periods for a monitor on a fileset
+    async fn recent_counts(&self, fileset_id: &FileSetId, monitor_id: &MonitorId) -> RecentCounts {
+        let filesets_data = self.filesets_data_rwlock.read().await;
+        filesets_data
+            .get(fileset_id)
+            .and_then(|fileset_data| fileset_data.monitor_data.get(monitor_id))
+            .map(|monitor_data| monitor_data.counts.recent())
+            .unwrap_or_default()
+    }
 }
 
 /// Start the notifier task. Listens for NotifierMessages
 pub(crate) async fn start_task(
     mut notifiers: HashMap<NotifierId, Notifier>,
+    context: NotifierContext,
+    mut reports: Reports,
 ) -> (Sender<NotifierMessage>, JoinHandle<()>) {
     let (tx, mut rx): (Sender<NotifierMessage>, Receiver<NotifierMessage>) = channel(32);
     let join_handle = tokio::spawn(async move {
         println!("Started notifier task");
-        while let Some(message) = rx.recv().await {
-            match message {
-                NotifierMessage::NotifyEvent(notifier_ids, ev_clone) => {
-                    for notifier_id in &notifier_ids {
-                        notify_event(
-                            notifiers
-                                .get_mut(notifier_id)
-                                .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id)),
-                            &ev_clone,
-                        )
-                        .await;
-                    }
+        let mut timer = tokio::time::interval(std::time::Duration::from_secs(10));
+        loop {
+            tokio::select! {
+                message = rx.recv() => {
+                    match message {
+                        Some(NotifierMessage::NotifyEvent(notifier_ids, fileset_id, monitor_id, ev_clone)) => {
+                            let mut notified = false;
+                            for notifier_id in &notifier_ids {
+                                notified |= notify_event(
+                                    notifiers
+                                        .get_mut(notifier_id)
+                                        .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id)),
+                                    notifier_id,
+                                    &fileset_id,
+                                    &monitor_id,
+                                    &ev_clone,
+                                    &context,
+                                )
+                                .await;
+                            }
+                            sync_open_incidents(&notifiers, &context).await;
+                            if notified {
+                                reports.record_alert(&fileset_id, &monitor_id);
+                                // Start escalating if nobody acknowledges the alert. Alerts which
+                                // were silenced, held back or rate limited everywhere don't escalate.
+                                if context.escalation_policies.contains(&fileset_id, &monitor_id) {
+                                    context
+                                        .escalations_rwlock
+                                        .write()
+                                        .await
+                                        .start_or_update(&fileset_id, &monitor_id, &ev_clone);
+                                }
+                            }
+                        }
+                        Some(NotifierMessage::NotifyMessage(notifier_ids, message)) => {
+                            for notifier_id in &notifier_ids {
+                                if let Some(silence) = context.silences_rwlock.read().await.matching_message(notifier_id) {
+                                    println!("Message via {} silenced by {}", notifier_id, silence.id);
+                                    continue;
+                                }
+                                notify_message(
+                                    notifiers
+                                        .get(notifier_id)
+                                        .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id)),
+                                    notifier_id,
+                                    &message,
+                                    &context,
+                                )
+                                .await;
+                            }
+                        }
+                        Some(NotifierMessage::Acknowledge(fileset_id, monitor_id, captures)) => {
+                            for (notifier_id, notifier) in &notifiers {
+                                for payload in notifier.back_end.acknowledge_payloads(&fileset_id, &monitor_id, &captures) {
+                                    send(notifier, notifier_id, payload, &context).await;
+                                }
+                            }
+                        }
+                        Some(NotifierMessage::Shutdown) | None => break,
+                    };
                 }
-                NotifierMessage::NotifyMessage(notifier_ids, message) => {
-                    for notifier_id in &notifier_ids {
-                        notifiers
-                            .get_mut(notifier_id)
-                            .unwrap_or_else(|| panic!("Invalid notifier ID {:?}", notifier_id))
-                            .back_end
-                            .notify_message(&message)
-                            .await;
+                _ = timer.tick() => {
+                    for (notifier_id, notifier) in notifiers.iter_mut() {
+                        notify_ended_maintenance_windows(notifier, notifier_id, &context).await;
+                        notify_digest_if_due(notifier, notifier_id, &context).await;
+                        notify_due_payloads(notifier, notifier_id, &context).await;
                     }
+                    notify_due_escalations(&mut notifiers, &context).await;
+                    notify_due_reports(&notifiers, &mut reports, &context).await;
+                    retry_pending_deliveries(&notifiers, &context).await;
+                    sync_open_incidents(&notifiers, &context).await;
                 }
-                NotifierMessage::Shutdown => break,
-            };
+            }
         }
         println!("Notifier task exiting...");
     });
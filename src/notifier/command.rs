@@ -0,0 +1,196 @@
+use crate::config::CommandNotifierConfig;
+use crate::data::MonitorEvent;
+use crate::delivery::DeliveryError;
+use crate::notifier::{BackEnd, EventContext};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// What's passed to the command as JSON on stdin, and stored in the outbox
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum CommandPayload {
+    Event {
+        fileset_id: String,
+        monitor_id: String,
+        hostname: String,
+        skipped: usize,
+        event: Box<MonitorEvent>,
+    },
+    Message {
+        message: String,
+    },
+}
+
+/// Runs a program for each notification
+pub struct CommandBackEnd {
+    pub(crate) config: CommandNotifierConfig,
+}
+
+impl CommandBackEnd {
+    pub(crate) fn new(config: CommandNotifierConfig) -> CommandBackEnd {
+        CommandBackEnd { config }
+    }
+
+    /// Environment variables describing the notification
+    fn env_vars(payload: &CommandPayload) -> Vec<(String, String)> {
+        match payload {
+            CommandPayload::Event {
+                fileset_id,
+                monitor_id,
+                hostname,
+                skipped,
+                event,
+            } => {
+                let mut vars = vec![
+                    ("CENTINELA_KIND".to_string(), "event".to_string()),
+                    ("CENTINELA_FILESET_ID".to_string(), fileset_id.clone()),
+                    ("CENTINELA_MONITOR_ID".to_string(), monitor_id.clone()),
+                    ("CENTINELA_HOSTNAME".to_string(), hostname.clone()),
+                    ("CENTINELA_SKIPPED".to_string(), skipped.to_string()),
+                    (
+                        "CENTINELA_SOURCE_FILE".to_string(),
+                        event.awaiting_lines_from.to_string_lossy().to_string(),
+                    ),
+                ];
+                if let Some(line) = event.lines.iter().find(|line| line.is_event_line) {
+                    vars.push(("CENTINELA_EVENT_LINE".to_string(), line.line.clone()));
+                }
+                for (name, value) in &event.captures {
+                    vars.push((
+                        format!("CENTINELA_CAPTURE_{}", name.to_uppercase()),
+                        value.clone(),
+                    ));
+                }
+                vars
+            }
+            CommandPayload::Message { message } => vec![
+                ("CENTINELA_KIND".to_string(), "message".to_string()),
+                ("CENTINELA_MESSAGE".to_string(), message.clone()),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl BackEnd for CommandBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        serde_json::to_string(&CommandPayload::Event {
+            fileset_id: event_context.fileset_id.clone(),
+            monitor_id: event_context.monitor_id.clone(),
+            hostname: event_context.hostname.to_string(),
+            skipped: event_context.skipped,
+            event: Box::new(event_context.event.clone()),
+        })
+        .expect("Failed to build JSON")
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        serde_json::to_string(&CommandPayload::Message {
+            message: message.to_owned(),
+        })
+        .expect("Failed to build JSON")
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let parsed: CommandPayload = serde_json::from_str(payload)
+            .map_err(|e| DeliveryError::permanent(format!("Invalid command payload: {}", e)))?;
+        let mut child = Command::new(&self.config.program)
+            .args(&self.config.args)
+            .envs(CommandBackEnd::env_vars(&parsed))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                DeliveryError::permanent(format!("Failed to run {:?}: {}", self.config.program, e))
+            })?;
+        let mut stdin = child.stdin.take().expect("Piped stdin");
+        let mut stderr_pipe = child.stderr.take().expect("Piped stderr");
+        let write_stdin = async move {
+            // A command which doesn't read stdin may close it early, which isn't an error.
+            // Stdin is closed when this finishes, so the command sees the end of the payload.
+            let _ = stdin.write_all(payload.as_bytes()).await;
+        };
+        let mut stderr = Vec::new();
+        let read_stderr = stderr_pipe.read_to_end(&mut stderr);
+        // The timeout covers writing stdin too, since a command which doesn't read it would
+        // otherwise block the write once the pipe is full
+        let timeout = std::time::Duration::from_secs(self.config.timeout as u64);
+        let status = match tokio::time::timeout(timeout, async {
+            let (_, _, status) = tokio::join!(write_stdin, read_stderr, child.wait());
+            status
+        })
+        .await
+        {
+            Ok(status) => status.map_err(|e| {
+                DeliveryError::transient(format!(
+                    "Failed waiting for {:?}: {}",
+                    self.config.program, e
+                ))
+            })?,
+            Err(_) => {
+                let _ = child.kill().await;
+                return Err(DeliveryError::transient(format!(
+                    "{:?} timed out after {}s",
+                    self.config.program, self.config.timeout
+                )));
+            }
+        };
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !stderr.trim().is_empty() {
+            println!("{:?} stderr: {}", self.config.program, stderr.trim_end());
+        }
+        if status.success() {
+            Ok(())
+        } else {
+            Err(DeliveryError::transient(format!(
+                "{:?} exited with {}",
+                self.config.program, status
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn back_end(script: &str) -> CommandBackEnd {
+        CommandBackEnd::new(
+            serde_json::from_value(json!({
+                "program": "sh",
+                "args": ["-c", script],
+                "timeout": 1,
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn deliver_times_out_if_stdin_isnt_read() {
+        // Much bigger than a pipe buffer, so the write blocks. The padding is ignored when the
+        // payload is parsed, so it isn't passed in the environment too.
+        let payload = json!({ "kind": "message", "message": "hi", "padding": "x".repeat(1 << 20) })
+            .to_string();
+        let started = std::time::Instant::now();
+        let error = back_end("sleep 10").deliver(&payload).await.unwrap_err();
+        assert!(error.message.contains("timed out"), "{}", error);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn deliver_passes_payload_on_stdin() {
+        let payload = back_end("").message_payload("hello");
+        back_end(r#"grep -q '"message":"hello"'"#)
+            .deliver(&payload)
+            .await
+            .unwrap();
+        let error = back_end("exit 3").deliver(&payload).await.unwrap_err();
+        assert!(error.message.contains("exited with"), "{}", error);
+    }
+}
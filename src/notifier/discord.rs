@@ -0,0 +1,99 @@
+use crate::config::{DiscordNotifierConfig, MessageLimitsConfig};
+use crate::delivery;
+use crate::delivery::DeliveryError;
+use crate::notifier::{truncate_chars, BackEnd, EventContext};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::{json, Value};
+
+/// Discord limits message content to 2000 characters
+const MAX_CONTENT_CHARS: usize = 2000;
+/// Discord limits embed titles to 256 characters
+const MAX_TITLE_CHARS: usize = 256;
+/// Discord limits embed descriptions to 4096 characters
+const MAX_DESCRIPTION_CHARS: usize = 4096;
+/// Red, for the bar down the side of embeds
+const EMBED_COLOUR: u32 = 0xE01E5A;
+
+/// Discord webhook
+pub struct DiscordBackEnd {
+    pub(crate) config: DiscordNotifierConfig,
+    pub(crate) client: reqwest::Client,
+}
+
+impl DiscordBackEnd {
+    pub(crate) fn new(config: DiscordNotifierConfig) -> DiscordBackEnd {
+        DiscordBackEnd {
+            config,
+            client: delivery::http_client(),
+        }
+    }
+
+    /// Add the options common to every message. Mentions in log lines are never
+    /// turned into pings.
+    fn to_json(&self, mut body: Value) -> String {
+        body["allowed_mentions"] = json!({ "parse": [] });
+        if let Some(username) = &self.config.username {
+            body["username"] = json!(username);
+        }
+        body.to_string()
+    }
+}
+
+#[async_trait]
+impl BackEnd for DiscordBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        let text = match &self.config.body_template {
+            Some(body_template) => {
+                event_context.render_or_else(body_template, EventContext::default_text)
+            }
+            None => event_context.default_text(),
+        };
+        if !self.config.embed {
+            return self.to_json(json!({ "content": truncate_chars(&text, MAX_CONTENT_CHARS) }));
+        }
+        self.to_json(json!({
+            "embeds": [{
+                "title": truncate_chars(&event_context.title(), MAX_TITLE_CHARS),
+                "description": truncate_chars(&text, MAX_DESCRIPTION_CHARS),
+                "color": EMBED_COLOUR,
+                "fields": [
+                    { "name": "Source file", "value": event_context.source_file, "inline": false },
+                    { "name": "Last hour", "value": event_context.counts.last_hour.to_string(), "inline": true },
+                    { "name": "Last day", "value": event_context.counts.last_day.to_string(), "inline": true },
+                ],
+                "timestamp": event_context
+                    .event_line
+                    .map(|line| line.date)
+                    .unwrap_or_else(Utc::now),
+            }]
+        }))
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        self.to_json(json!({ "content": truncate_chars(message, MAX_CONTENT_CHARS) }))
+    }
+
+    /// Leaves room within Discord's limits for the rest of the message
+    fn default_limits(&self) -> MessageLimitsConfig {
+        MessageLimitsConfig {
+            max_chars: Some(match self.config.embed {
+                true => MAX_DESCRIPTION_CHARS - 100,
+                false => MAX_CONTENT_CHARS - 100,
+            }),
+            max_line_chars: Some(500),
+            split: false,
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let res = self
+            .client
+            .post(self.config.url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await;
+        delivery::check_response(res)
+    }
+}
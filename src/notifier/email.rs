@@ -0,0 +1,166 @@
+use crate::config::{EmailNotifierConfig, OutputFormat, SmtpTls};
+use crate::delivery::DeliveryError;
+use crate::notifier::{BackEnd, EventContext};
+use crate::template::Template;
+use async_trait::async_trait;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+
+/// Email contents, as stored in the outbox
+#[derive(Serialize, Deserialize)]
+struct EmailPayload {
+    subject: String,
+    text: String,
+    html: Option<String>,
+}
+
+/// SMTP email
+pub struct EmailBackEnd {
+    config: EmailNotifierConfig,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    cc: Vec<Mailbox>,
+    subject_template: Template,
+}
+
+impl EmailBackEnd {
+    /// Create the back-end, checking addresses and resolving credentials
+    pub(crate) fn new(config: EmailNotifierConfig) -> Result<EmailBackEnd, String> {
+        let parse_mailbox = |address: &String| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| format!("invalid address {}: {}", address, e))
+        };
+        let from = parse_mailbox(&config.from)?;
+        let to = config
+            .to
+            .iter()
+            .map(parse_mailbox)
+            .collect::<Result<Vec<Mailbox>, String>>()?;
+        let cc = config
+            .cc
+            .iter()
+            .map(parse_mailbox)
+            .collect::<Result<Vec<Mailbox>, String>>()?;
+        if to.is_empty() {
+            return Err("at least one to address is required".to_string());
+        }
+
+        let mut builder = match config.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host),
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host),
+            SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &config.host,
+            )),
+        }
+        .map_err(|e| format!("invalid SMTP host {}: {}", config.host, e))?
+        .timeout(Some(std::time::Duration::from_secs(30)));
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        match (&config.username, &config.password) {
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(
+                    username.resolve().map_err(|e| format!("username: {}", e))?,
+                    password.resolve().map_err(|e| format!("password: {}", e))?,
+                ));
+            }
+            (None, None) => {}
+            _ => return Err("username and password must be given together".to_string()),
+        }
+
+        let subject_template = match &config.subject_template {
+            Some(subject_template) => subject_template.clone(),
+            None => Template::new("Centinela: {{ monitor_id }} on {{ fileset_id }}".to_string())
+                .expect("Valid default subject template"),
+        };
+        Ok(EmailBackEnd {
+            transport: builder.build(),
+            config,
+            from,
+            to,
+            cc,
+            subject_template,
+        })
+    }
+}
+
+#[async_trait]
+impl BackEnd for EmailBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        let subject = event_context.render_or_else(&self.subject_template, |context| {
+            format!(
+                "Centinela: {} on {}",
+                context.monitor_id, context.fileset_id
+            )
+        });
+        let text = match &self.config.body_template {
+            Some(body_template) => {
+                event_context.render_or_else(body_template, EventContext::default_text)
+            }
+            None => event_context.default_text(),
+        };
+        let html = match self.config.html {
+            true => Some(event_context.lines_html.clone()),
+            false => None,
+        };
+        serde_json::to_string(&EmailPayload {
+            // Subjects are single line
+            subject: subject.lines().collect::<Vec<&str>>().join(" "),
+            text,
+            html,
+        })
+        .expect("Failed to build JSON")
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        serde_json::to_string(&EmailPayload {
+            subject: "Centinela notification".to_string(),
+            text: message.to_owned(),
+            html: None,
+        })
+        .expect("Failed to build JSON")
+    }
+
+    fn report_payload(&self, title: &str, text: &str, html: Option<&str>) -> String {
+        serde_json::to_string(&EmailPayload {
+            subject: title.to_string(),
+            text: text.to_owned(),
+            html: html.map(|html| html.to_owned()),
+        })
+        .expect("Failed to build JSON")
+    }
+
+    fn default_format(&self) -> OutputFormat {
+        OutputFormat::Plain
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let payload: EmailPayload = serde_json::from_str(payload)
+            .map_err(|e| DeliveryError::permanent(format!("Invalid email payload: {}", e)))?;
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(payload.subject);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        for cc in &self.cc {
+            builder = builder.cc(cc.clone());
+        }
+        let message = match payload.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(payload.text, html)),
+            None => builder.body(payload.text),
+        }
+        .map_err(|e| DeliveryError::permanent(format!("Failed to build email: {}", e)))?;
+        match self.transport.send(message).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.is_permanent() => {
+                Err(DeliveryError::permanent(format!("SMTP error: {}", e)))
+            }
+            Err(e) => Err(DeliveryError::transient(format!("SMTP error: {}", e))),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use crate::config::{FailoverNotifierConfig, MessageLimitsConfig, NotifierConfig, OutputFormat};
+use crate::delivery::DeliveryError;
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::{BackEnd, EventContext, NotifierId, OpenIncidents};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A notifier in a failover chain, with the failover's own copy of its back-end
+pub(crate) struct FailoverMember {
+    pub(crate) notifier_id: NotifierId,
+    pub(crate) config: NotifierConfig,
+    pub(crate) back_end: Box<dyn BackEnd + Sync + Send>,
+}
+
+/// The payload for one member. A failover payload is the list of these for every member, so
+/// queued deliveries retry the whole chain.
+#[derive(Serialize, Deserialize)]
+struct MemberPayload {
+    notifier_id: NotifierId,
+    payload: String,
+}
+
+/// Tries a list of notifiers in turn until one succeeds. Notifiers which fail are skipped
+/// until their cooldown has passed. Members' own rate limits, silences and maintenance windows
+/// don't apply, only the failover notifier's.
+pub struct FailoverBackEnd {
+    pub(crate) config: FailoverNotifierConfig,
+    members: Vec<FailoverMember>,
+    /// When each failing member stops being skipped
+    cooling_down: Mutex<HashMap<NotifierId, DateTime<Utc>>>,
+}
+
+impl FailoverBackEnd {
+    pub(crate) fn new(
+        config: FailoverNotifierConfig,
+        members: Vec<FailoverMember>,
+    ) -> FailoverBackEnd {
+        FailoverBackEnd {
+            config,
+            members,
+            cooling_down: Default::default(),
+        }
+    }
+
+    /// One payload per member, as JSON
+    fn payloads<F>(&self, member_payload: F) -> String
+    where
+        F: Fn(&FailoverMember) -> String,
+    {
+        let payloads = self
+            .members
+            .iter()
+            .map(|member| MemberPayload {
+                notifier_id: member.notifier_id.clone(),
+                payload: member_payload(member),
+            })
+            .collect::<Vec<MemberPayload>>();
+        serde_json::to_string(&payloads).expect("Failed to encode failover payloads")
+    }
+
+    /// Payloads from individual members, each to be sent via that member alone
+    fn member_payloads<F>(&self, member_payloads: F) -> Vec<String>
+    where
+        F: Fn(&FailoverMember) -> Vec<String>,
+    {
+        self.members
+            .iter()
+            .flat_map(|member| {
+                member_payloads(member).into_iter().map(|payload| {
+                    serde_json::to_string(&[MemberPayload {
+                        notifier_id: member.notifier_id.clone(),
+                        payload,
+                    }])
+                    .expect("Failed to encode failover payloads")
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl BackEnd for FailoverBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        self.payloads(|member| member.back_end.event_payload(event_context))
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        self.payloads(|member| member.back_end.message_payload(message))
+    }
+
+    fn report_payload(&self, title: &str, text: &str, html: Option<&str>) -> String {
+        self.payloads(|member| member.back_end.report_payload(title, text, html))
+    }
+
+    /// The tightest of the members' limits, since the lines are laid out once for all of them
+    fn default_limits(&self) -> MessageLimitsConfig {
+        self.members
+            .iter()
+            .map(|member| {
+                member
+                    .config
+                    .common()
+                    .limits
+                    .or(member.back_end.default_limits())
+            })
+            .fold(Default::default(), |tightest, limits| MessageLimitsConfig {
+                max_chars: min_of(tightest.max_chars, limits.max_chars),
+                max_line_chars: min_of(tightest.max_line_chars, limits.max_line_chars),
+                split: tightest.split || limits.split,
+            })
+    }
+
+    /// The first member's format
+    fn default_format(&self) -> OutputFormat {
+        self.members
+            .first()
+            .map(|member| {
+                member
+                    .config
+                    .common()
+                    .format
+                    .unwrap_or_else(|| member.back_end.default_format())
+            })
+            .unwrap_or(OutputFormat::Markdown)
+    }
+
+    fn event_seen(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        captures: &HashMap<String, String>,
+    ) {
+        for member in &self.members {
+            member.back_end.event_seen(fileset_id, monitor_id, captures);
+        }
+    }
+
+    /// Anything the members have scheduled, each sent via that member alone
+    fn due_payloads(&self) -> Vec<String> {
+        self.member_payloads(|member| member.back_end.due_payloads())
+    }
+
+    fn acknowledge_payloads(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        captures: &HashMap<String, String>,
+    ) -> Vec<String> {
+        self.member_payloads(|member| {
+            member
+                .back_end
+                .acknowledge_payloads(fileset_id, monitor_id, captures)
+        })
+    }
+
+    /// The members' open incidents combined
+    fn open_incidents(&self) -> OpenIncidents {
+        self.members
+            .iter()
+            .flat_map(|member| member.back_end.open_incidents())
+            .collect()
+    }
+
+    /// Each member is given all of the incidents, since they aren't saved separately. Resolving
+    /// an incident a member didn't open does nothing.
+    fn restore_open_incidents(&self, open_incidents: OpenIncidents) {
+        for member in &self.members {
+            member
+                .back_end
+                .restore_open_incidents(open_incidents.clone());
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let payloads: Vec<MemberPayload> = serde_json::from_str(payload)
+            .map_err(|e| DeliveryError::permanent(format!("Invalid failover payload: {}", e)))?;
+        // Members which failed recently are skipped until their cooldown has passed. If they
+        // all have, the one which failed longest ago is tried rather than none.
+        let cooling_down = self.cooling_down.lock().expect("unpoisoned lock").clone();
+        let mut order = payloads
+            .iter()
+            .filter(|member_payload| !is_cooling_down(&cooling_down, &member_payload.notifier_id))
+            .collect::<Vec<&MemberPayload>>();
+        if order.is_empty() {
+            order.extend(
+                payloads
+                    .iter()
+                    .min_by_key(|member_payload| cooling_down.get(&member_payload.notifier_id)),
+            );
+        }
+        let mut errors = vec![];
+        for member_payload in order {
+            let member = match self
+                .members
+                .iter()
+                .find(|member| member.notifier_id == member_payload.notifier_id)
+            {
+                Some(member) => member,
+                None => continue,
+            };
+            match member.back_end.deliver(&member_payload.payload).await {
+                Ok(()) => {
+                    println!("Failover delivered via {}", member.notifier_id);
+                    self.cooling_down
+                        .lock()
+                        .expect("unpoisoned lock")
+                        .remove(&member.notifier_id);
+                    return Ok(());
+                }
+                Err(error) => {
+                    println!("Failover via {} failed: {}", member.notifier_id, error);
+                    let now = Utc::now();
+                    let until = i64::try_from(self.config.cooldown)
+                        .ok()
+                        .and_then(Duration::try_seconds)
+                        .and_then(|cooldown| now.checked_add_signed(cooldown))
+                        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+                    self.cooling_down
+                        .lock()
+                        .expect("unpoisoned lock")
+                        .insert(member.notifier_id.clone(), until);
+                    errors.push((member.notifier_id.clone(), error));
+                }
+            }
+        }
+        Err(DeliveryError {
+            message: format!(
+                "All notifiers failed: {}",
+                errors
+                    .iter()
+                    .map(|(notifier_id, error)| format!("{}: {}", notifier_id, error))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            retry_after: None,
+            // Worth retrying unless every member rejected the notification outright
+            permanent: !errors.is_empty() && errors.iter().all(|(_, error)| error.permanent),
+        })
+    }
+}
+
+fn is_cooling_down(
+    cooling_down: &HashMap<NotifierId, DateTime<Utc>>,
+    notifier_id: &NotifierId,
+) -> bool {
+    cooling_down
+        .get(notifier_id)
+        .is_some_and(|until| *until > Utc::now())
+}
+
+/// The smaller of two optional limits, where None means no limit
+fn min_of(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Records deliveries, failing them if told to
+    struct TestBackEnd {
+        fail: bool,
+        attempts: Arc<Mutex<usize>>,
+    }
+
+    #[async_trait]
+    impl BackEnd for TestBackEnd {
+        fn event_payload(&self, _event_context: &EventContext) -> String {
+            String::new()
+        }
+
+        fn message_payload(&self, message: &str) -> String {
+            message.to_string()
+        }
+
+        async fn deliver(&self, _payload: &str) -> Result<(), DeliveryError> {
+            *self.attempts.lock().unwrap() += 1;
+            if self.fail {
+                Err(DeliveryError::transient("down".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// A failover chain with members that fail or not, and the attempts made on each
+    fn failover(fails: &[bool]) -> (FailoverBackEnd, Vec<Arc<Mutex<usize>>>) {
+        let mut attempts = vec![];
+        let members = fails
+            .iter()
+            .enumerate()
+            .map(|(index, fail)| {
+                let member_attempts = Arc::new(Mutex::new(0));
+                attempts.push(member_attempts.clone());
+                FailoverMember {
+                    notifier_id: format!("member{}", index),
+                    config: serde_json::from_value(
+                        json!({ "webhook": { "url": "http://localhost/" } }),
+                    )
+                    .unwrap(),
+                    back_end: Box::new(TestBackEnd {
+                        fail: *fail,
+                        attempts: member_attempts,
+                    }),
+                }
+            })
+            .collect();
+        let config = serde_json::from_value(json!({ "notifiers": [], "cooldown": 60 })).unwrap();
+        (FailoverBackEnd::new(config, members), attempts)
+    }
+
+    fn counts(attempts: &[Arc<Mutex<usize>>]) -> Vec<usize> {
+        attempts
+            .iter()
+            .map(|attempts| *attempts.lock().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skips_members_cooling_down() {
+        let (failover, attempts) = failover(&[true, false]);
+        let payload = failover.message_payload("hello");
+        failover.deliver(&payload).await.unwrap();
+        failover.deliver(&payload).await.unwrap();
+        assert_eq!(counts(&attempts), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn tries_member_which_failed_longest_ago_when_all_are_cooling_down() {
+        let (failover, attempts) = failover(&[true, true]);
+        let payload = failover.message_payload("hello");
+        assert!(failover.deliver(&payload).await.is_err());
+        assert_eq!(counts(&attempts), vec![1, 1]);
+        assert!(failover.deliver(&payload).await.is_err());
+        assert_eq!(counts(&attempts), vec![2, 1]);
+        assert!(failover.deliver(&payload).await.is_err());
+        assert_eq!(counts(&attempts), vec![2, 2]);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::config::FileNotifierConfig;
+use crate::data::LogLine;
+use crate::delivery::DeliveryError;
+use crate::notifier::{BackEnd, EventContext};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Format of the timestamp appended to rotated files' names
+const ROTATED_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+
+/// A single line in the file
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum FileRecord {
+    Event {
+        timestamp: DateTime<Utc>,
+        fileset_id: String,
+        monitor_id: String,
+        hostname: String,
+        source_file: String,
+        lines: Vec<LogLine>,
+        captures: HashMap<String, String>,
+        skipped: usize,
+    },
+    Message {
+        timestamp: DateTime<Utc>,
+        message: String,
+    },
+}
+
+/// Appends notifications to a file as JSON lines
+pub struct FileBackEnd {
+    writer: Arc<FileWriter>,
+}
+
+/// Writes and rotates the file. Shared with blocking tasks so file IO stays off the notifier
+/// task.
+struct FileWriter {
+    config: FileNotifierConfig,
+    /// When the current file was started, for time based rotation
+    started: Mutex<DateTime<Utc>>,
+}
+
+impl FileBackEnd {
+    pub(crate) fn new(config: FileNotifierConfig) -> FileBackEnd {
+        let started = fs::metadata(&config.path)
+            .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()))
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        FileBackEnd {
+            writer: Arc::new(FileWriter {
+                config,
+                started: Mutex::new(started),
+            }),
+        }
+    }
+}
+
+impl FileWriter {
+    /// Rotate the file if due, then append a line to it
+    fn write(&self, payload: &str) -> io::Result<()> {
+        let mut started = self.started.lock().expect("unpoisoned lock");
+        if let Err(e) = self.rotate_if_due(&mut started) {
+            println!("Failed to rotate {:?}: {}", self.config.path, e);
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .and_then(|mut file| file.write_all((payload.to_owned() + "\n").as_bytes()))
+    }
+
+    /// Rotate the file if it's grown too large or too old
+    fn rotate_if_due(&self, started: &mut DateTime<Utc>) -> io::Result<()> {
+        let size = match fs::metadata(&self.config.path) {
+            Ok(metadata) => metadata.len(),
+            // Nothing to rotate yet
+            Err(_) => {
+                *started = Utc::now();
+                return Ok(());
+            }
+        };
+        let too_large = self
+            .config
+            .max_size
+            .is_some_and(|max_size| size >= max_size);
+        // A rotate_every too long to represent never comes round
+        let too_old = self
+            .config
+            .rotate_every
+            .and_then(|rotate_every| i64::try_from(rotate_every).ok())
+            .and_then(Duration::try_seconds)
+            .and_then(|rotate_every| Utc::now().checked_sub_signed(rotate_every))
+            .is_some_and(|cutoff| cutoff >= *started);
+        if !(too_large || too_old) || size == 0 {
+            return Ok(());
+        }
+
+        let mut rotated_path = self.config.path.clone().into_os_string();
+        rotated_path.push(format!(".{}", Utc::now().format(ROTATED_TIMESTAMP_FORMAT)));
+        let rotated_path = PathBuf::from(rotated_path);
+        fs::rename(&self.config.path, &rotated_path)?;
+        *started = Utc::now();
+        if self.config.gzip {
+            let mut gz_path = rotated_path.clone().into_os_string();
+            gz_path.push(".gz");
+            let mut encoder = GzEncoder::new(File::create(gz_path)?, Compression::default());
+            io::copy(&mut File::open(&rotated_path)?, &mut encoder)?;
+            encoder.finish()?;
+            fs::remove_file(&rotated_path)?;
+        }
+        println!("Rotated {:?}", self.config.path);
+        self.remove_old_rotated_files()
+    }
+
+    /// Delete the oldest rotated files beyond the number to keep. Only files named as this
+    /// notifier names them are counted or deleted.
+    fn remove_old_rotated_files(&self) -> io::Result<()> {
+        let keep = match self.config.keep {
+            Some(keep) => keep,
+            None => return Ok(()),
+        };
+        let (dir, file_name) = match (self.config.path.parent(), self.config.path.file_name()) {
+            (Some(dir), Some(file_name)) => (dir, file_name.to_string_lossy().to_string()),
+            _ => return Ok(()),
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir.to_path_buf()
+        };
+        let mut rotated = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_rotated_name(&entry.file_name().to_string_lossy(), &file_name))
+            .map(|entry| entry.path())
+            .collect::<Vec<PathBuf>>();
+        // Timestamped names sort oldest first
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(keep);
+        for path in rotated.iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` is `file_name` plus a rotation timestamp, and .gz if compressed
+fn is_rotated_name(name: &str, file_name: &str) -> bool {
+    let timestamp = match name
+        .strip_prefix(file_name)
+        .and_then(|suffix| suffix.strip_prefix('.'))
+    {
+        Some(suffix) => suffix.strip_suffix(".gz").unwrap_or(suffix),
+        None => return false,
+    };
+    // e.g. 20240131T235959.123
+    timestamp.len() == 19
+        && NaiveDateTime::parse_from_str(timestamp, ROTATED_TIMESTAMP_FORMAT).is_ok()
+}
+
+#[async_trait]
+impl BackEnd for FileBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        serde_json::to_string(&FileRecord::Event {
+            timestamp: Utc::now(),
+            fileset_id: event_context.fileset_id.clone(),
+            monitor_id: event_context.monitor_id.clone(),
+            hostname: event_context.hostname.to_string(),
+            source_file: event_context.source_file.clone(),
+            lines: event_context.lines.to_vec(),
+            captures: event_context.captures.clone(),
+            skipped: event_context.skipped,
+        })
+        .expect("Failed to build JSON")
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        serde_json::to_string(&FileRecord::Message {
+            timestamp: Utc::now(),
+            message: message.to_owned(),
+        })
+        .expect("Failed to build JSON")
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let writer = self.writer.clone();
+        let payload = payload.to_owned();
+        let path = writer.config.path.clone();
+        tokio::task::spawn_blocking(move || writer.write(&payload))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)))
+            .map_err(|e| DeliveryError::transient(format!("Failed to write {:?}: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A new, empty directory for a test's files
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("centinela-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn back_end(dir: &std::path::Path, options: serde_json::Value) -> FileBackEnd {
+        let mut config = json!({ "path": dir.join("app.log") });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(options.as_object().unwrap().clone());
+        FileBackEnd::new(serde_json::from_value(config).unwrap())
+    }
+
+    fn file_names(dir: &std::path::Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn is_rotated_name_matches_only_rotation_suffixes() {
+        assert!(is_rotated_name("app.log.20240131T235959.123", "app.log"));
+        assert!(is_rotated_name("app.log.20240131T235959.123.gz", "app.log"));
+        for name in [
+            "app.log",
+            "app.log.1",
+            "app.log.bak",
+            "app.log.2024-01-31",
+            "app.log.20240131T235959.123.bak",
+            "app.log.20240131T235959.123456",
+            "other.log.20240131T235959.123",
+        ] {
+            assert!(!is_rotated_name(name, "app.log"), "{}", name);
+        }
+        assert!(!is_rotated_name("name.json", "name"));
+    }
+
+    #[tokio::test]
+    async fn rotates_by_size_and_keeps_only_rotated_files_it_wrote() {
+        let dir = temp_dir();
+        for unrelated in ["app.log.1", "app.log.bak"] {
+            fs::write(dir.join(unrelated), "keep me\n").unwrap();
+        }
+        let back_end = back_end(&dir, json!({ "max_size": 4, "keep": 1 }));
+        for payload in ["one", "two", "three"] {
+            back_end.deliver(payload).await.unwrap();
+            // Rotated names have millisecond timestamps
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let names = file_names(&dir);
+        let rotated = names
+            .iter()
+            .filter(|name| is_rotated_name(name, "app.log"))
+            .collect::<Vec<&String>>();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(fs::read_to_string(dir.join(rotated[0])).unwrap(), "two\n");
+        assert_eq!(fs::read_to_string(dir.join("app.log")).unwrap(), "three\n");
+        assert!(names.contains(&"app.log.1".to_string()));
+        assert!(names.contains(&"app.log.bak".to_string()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn compresses_rotated_files() {
+        let dir = temp_dir();
+        let back_end = back_end(&dir, json!({ "max_size": 1, "gzip": true }));
+        back_end.deliver("one").await.unwrap();
+        back_end.deliver("two").await.unwrap();
+        let names = file_names(&dir);
+        assert_eq!(names.len(), 2);
+        assert!(names[1].ends_with(".gz") && is_rotated_name(&names[1], "app.log"));
+        let mut decoded = String::new();
+        io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(File::open(dir.join(&names[1])).unwrap()),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, "one\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_by_age_and_ignores_huge_rotate_every() {
+        let dir = temp_dir();
+        let never = back_end(&dir, json!({ "rotate_every": usize::MAX }));
+        never.deliver("one").await.unwrap();
+        never.deliver("two").await.unwrap();
+        assert_eq!(file_names(&dir), vec!["app.log"]);
+        let always = back_end(&dir, json!({ "rotate_every": 0 }));
+        always.deliver("three").await.unwrap();
+        assert_eq!(file_names(&dir).len(), 2);
+        assert_eq!(fs::read_to_string(dir.join("app.log")).unwrap(), "three\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,602 @@
+use crate::config::{MessageLimitsConfig, OutputFormat};
+use crate::data::{escape_html, LogLine};
+use std::ops::Range;
+
+/// Longest run of highlight characters around the event line
+const MAX_HIGHLIGHT_WIDTH: usize = 100;
+
+/// A line as it will appear in a notification, or a marker for lines left out
+#[derive(Clone, Debug)]
+pub(crate) struct DisplayLine {
+    pub(crate) text: String,
+    pub(crate) is_event_line: bool,
+}
+
+impl DisplayLine {
+    fn from_log_line(line: &LogLine, max_line_chars: Option<usize>) -> DisplayLine {
+        let text = sanitise(&line.to_string());
+        let text = match max_line_chars {
+            Some(max_line_chars) if text.chars().count() > max_line_chars => {
+                text.chars()
+                    .take(max_line_chars.saturating_sub(1))
+                    .collect::<String>()
+                    + "…"
+            }
+            _ => text,
+        };
+        DisplayLine {
+            text,
+            is_event_line: line.is_event_line,
+        }
+    }
+
+    fn omitted(count: usize) -> DisplayLine {
+        DisplayLine {
+            text: format!("… {} lines omitted …", count),
+            is_event_line: false,
+        }
+    }
+}
+
+/// A group of event lines to be sent in one notification
+pub(crate) struct LinesPart<'a> {
+    /// The original lines covered by this part
+    pub(crate) lines: &'a [LogLine],
+    /// The lines as they should be displayed, after truncation and elision
+    pub(crate) display_lines: Vec<DisplayLine>,
+    /// Position of this part, counting from 1
+    pub(crate) number: usize,
+    pub(crate) total: usize,
+    /// How the notifier presents the lines
+    pub(crate) output_format: OutputFormat,
+}
+
+/// Lay out event lines within the limits. Long lines are truncated, then if the lines are
+/// still too long either lines are left out of the middle of the context either side of the
+/// event line, or they're split into several parts. Sizes are measured in the output format.
+pub(crate) fn layout<'a>(
+    lines: &'a [LogLine],
+    limits: &MessageLimitsConfig,
+    output_format: OutputFormat,
+) -> Vec<LinesPart<'a>> {
+    let display_lines = lines
+        .iter()
+        .map(|line| DisplayLine::from_log_line(line, limits.max_line_chars))
+        .collect::<Vec<DisplayLine>>();
+    let max_chars = match limits.max_chars {
+        Some(max_chars) if char_count(&display_lines, output_format) > max_chars => max_chars,
+        _ => {
+            return vec![LinesPart {
+                lines,
+                display_lines,
+                number: 1,
+                total: 1,
+                output_format,
+            }]
+        }
+    };
+    let parts = if limits.split {
+        split(display_lines, max_chars, output_format)
+    } else {
+        vec![(
+            0..lines.len(),
+            elide(display_lines, max_chars, output_format),
+        )]
+    };
+    let total = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, (range, display_lines))| LinesPart {
+            lines: &lines[range],
+            display_lines,
+            number: index + 1,
+            total,
+            output_format,
+        })
+        .collect()
+}
+
+/// Leave lines out of the middle of the context before and after the event line until
+/// the block fits. The event line itself is always kept.
+fn elide(
+    display_lines: Vec<DisplayLine>,
+    max_chars: usize,
+    output_format: OutputFormat,
+) -> Vec<DisplayLine> {
+    let (before, event, after) = match display_lines.iter().position(|line| line.is_event_line) {
+        Some(index) => (
+            &display_lines[..index],
+            &display_lines[index..=index],
+            &display_lines[index + 1..],
+        ),
+        None => (&display_lines[..], &display_lines[..0], &display_lines[..0]),
+    };
+    let measure = Measure::new(output_format);
+    let (before_chars, after_chars) = (
+        RunChars::new(before, &measure),
+        RunChars::new(after, &measure),
+    );
+    let event_chars = event.iter().map(|line| measure.line(line)).sum::<usize>();
+    let (mut keep_before, mut keep_after) = (before.len(), after.len());
+    loop {
+        let (before_kept, before_count) = before_chars.keep_ends(keep_before);
+        let (after_kept, after_count) = after_chars.keep_ends(keep_after);
+        let chars = measure.block(
+            before_kept + event_chars + after_kept,
+            before_count + event.len() + after_count,
+        );
+        if (keep_before == 0 && keep_after == 0) || chars <= max_chars {
+            return [
+                keep_ends(before, keep_before),
+                event.to_vec(),
+                keep_ends(after, keep_after),
+            ]
+            .concat();
+        }
+        if keep_before >= keep_after {
+            keep_before -= 1;
+        } else {
+            keep_after -= 1;
+        }
+    }
+}
+
+/// Keep `keep` lines from the start and end of a run, replacing those in between with a marker
+fn keep_ends(lines: &[DisplayLine], keep: usize) -> Vec<DisplayLine> {
+    if keep >= lines.len() {
+        return lines.to_vec();
+    }
+    let head = keep.div_ceil(2);
+    let tail = keep / 2;
+    [
+        &lines[..head],
+        &[DisplayLine::omitted(lines.len() - keep)],
+        &lines[lines.len() - tail..],
+    ]
+    .concat()
+}
+
+/// Split lines into consecutive groups which each fit, as far as possible
+fn split(
+    display_lines: Vec<DisplayLine>,
+    max_chars: usize,
+    output_format: OutputFormat,
+) -> Vec<(Range<usize>, Vec<DisplayLine>)> {
+    let measure = Measure::new(output_format);
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut current = vec![];
+    // Characters taken by the lines in current, not counting the block around them
+    let mut current_chars = 0;
+    for (index, line) in display_lines.into_iter().enumerate() {
+        let line_chars = measure.line(&line);
+        if !current.is_empty()
+            && measure.block(current_chars + line_chars, current.len() + 1) > max_chars
+        {
+            parts.push((start..index, std::mem::take(&mut current)));
+            start = index;
+            current_chars = 0;
+        }
+        current_chars += line_chars;
+        current.push(line);
+    }
+    parts.push((start..start + current.len(), current));
+    parts
+}
+
+fn char_count(display_lines: &[DisplayLine], output_format: OutputFormat) -> usize {
+    render(display_lines, output_format).chars().count()
+}
+
+/// Measures blocks of lines without rendering them as a whole. A block is the format's fixed
+/// wrapping, each line as rendered on its own, and a newline between each pair of lines.
+struct Measure {
+    output_format: OutputFormat,
+    /// Characters in an empty block
+    overhead: usize,
+}
+
+impl Measure {
+    fn new(output_format: OutputFormat) -> Measure {
+        Measure {
+            output_format,
+            overhead: char_count(&[], output_format),
+        }
+    }
+
+    /// Characters a line adds to a block
+    fn line(&self, line: &DisplayLine) -> usize {
+        char_count(std::slice::from_ref(line), self.output_format) - self.overhead
+    }
+
+    /// Characters in a block of `count` lines which add `line_chars` between them
+    fn block(&self, line_chars: usize, count: usize) -> usize {
+        self.overhead + line_chars + count.saturating_sub(1)
+    }
+}
+
+/// Running totals of the characters in a run of lines, so the size of the run after
+/// keep_ends() can be found without building it
+struct RunChars<'a> {
+    measure: &'a Measure,
+    /// Characters in the first n lines, for each n from 0 to the length of the run
+    totals: Vec<usize>,
+}
+
+impl<'a> RunChars<'a> {
+    fn new(lines: &[DisplayLine], measure: &'a Measure) -> RunChars<'a> {
+        let mut totals = Vec::with_capacity(lines.len() + 1);
+        totals.push(0);
+        for line in lines {
+            totals.push(totals[totals.len() - 1] + measure.line(line));
+        }
+        RunChars { measure, totals }
+    }
+
+    /// Characters added by, and number of, the lines keep_ends() would return
+    fn keep_ends(&self, keep: usize) -> (usize, usize) {
+        let len = self.totals.len() - 1;
+        if keep >= len {
+            return (self.totals[len], len);
+        }
+        let head = self.totals[keep.div_ceil(2)];
+        let tail = self.totals[len] - self.totals[len - keep / 2];
+        let marker = self.measure.line(&DisplayLine::omitted(len - keep));
+        (head + marker + tail, keep + 1)
+    }
+}
+
+/// Lines in the given format
+pub(crate) fn render(display_lines: &[DisplayLine], output_format: OutputFormat) -> String {
+    match output_format {
+        OutputFormat::Markdown => markdown_block(display_lines),
+        OutputFormat::Plain => plain_block(display_lines),
+        OutputFormat::Html => html_block(display_lines),
+    }
+}
+
+/// Lines as a markdown code block, highlighting the event line. Anything which would end the
+/// code block early or mention people is escaped.
+pub(crate) fn markdown_block(display_lines: &[DisplayLine]) -> String {
+    "\n```\n".to_string()
+        + &highlighted_lines(display_lines, |text| {
+            neutralise_mentions(&text.replace("```", "`\u{200B}`\u{200B}`"))
+        })
+        + "\n```\n"
+}
+
+/// Lines as plain text, highlighting the event line
+pub(crate) fn plain_block(display_lines: &[DisplayLine]) -> String {
+    highlighted_lines(display_lines, neutralise_mentions) + "\n"
+}
+
+/// Lines as preformatted HTML, highlighting the event line
+pub(crate) fn html_block(display_lines: &[DisplayLine]) -> String {
+    "<pre>".to_string()
+        + display_lines
+            .iter()
+            .map(|line| {
+                let text = escape_html(&line.text);
+                if line.is_event_line {
+                    format!("<strong style=\"background-color: #fdd\">{}</strong>", text)
+                } else {
+                    text
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+            .as_str()
+        + "</pre>"
+}
+
+/// Lines one per row, with rows of v and ^ above and below the event line
+fn highlighted_lines<F>(display_lines: &[DisplayLine], escape: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    display_lines
+        .iter()
+        .map(|line| {
+            let text = escape(&line.text);
+            if line.is_event_line {
+                let width = line.text.chars().count().min(MAX_HIGHLIGHT_WIDTH);
+                format!("\n{}\n{}\n{}\n", "v".repeat(width), text, "^".repeat(width))
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Remove ANSI escape sequences, such as colour codes, and control characters other than tabs
+pub(crate) fn sanitise(text: &str) -> String {
+    let mut sanitised = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1B}' => match chars.next() {
+                // Control sequence, ended by a character in the range @ to ~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // Operating system command, ended by BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' {
+                            break;
+                        }
+                        if c == '\u{1B}' {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Other sequences are a single character after the ESC
+                _ => {}
+            },
+            '\t' => sanitised.push(c),
+            c if c.is_control() => {}
+            c => sanitised.push(c),
+        }
+    }
+    sanitised
+}
+
+/// Stop chat services turning text into mentions, such as @channel, <!here> or <@U123>, by
+/// inserting a zero width space. Email addresses are left alone.
+fn neutralise_mentions(text: &str) -> String {
+    let mut neutralised = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+    for c in text.chars() {
+        let after_bracket = previous == Some('<');
+        // Slack style <!here> and <@U123>
+        if (c == '!' || c == '@') && after_bracket {
+            neutralised.push('\u{200B}');
+        }
+        neutralised.push(c);
+        // A bare @channel or @name
+        if c == '@' && !after_bracket && !previous.is_some_and(char::is_alphanumeric) {
+            neutralised.push('\u{200B}');
+        }
+        previous = Some(c);
+    }
+    neutralised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn display_lines(texts: &[&str], event_index: Option<usize>) -> Vec<DisplayLine> {
+        texts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| DisplayLine {
+                text: text.to_string(),
+                is_event_line: Some(index) == event_index,
+            })
+            .collect()
+    }
+
+    fn texts(display_lines: &[DisplayLine]) -> Vec<&str> {
+        display_lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn split_covers_every_line_once() {
+        // Each line is 5 characters in plain text, including its newline
+        let lines = display_lines(&["aaaa", "bbbb", "cccc", "dddd", "eeee"], None);
+        let parts = split(lines, 10, OutputFormat::Plain);
+        let ranges = parts
+            .iter()
+            .map(|(range, _)| range.clone())
+            .collect::<Vec<Range<usize>>>();
+        assert_eq!(ranges, vec![0..2, 2..4, 4..5]);
+        assert_eq!(texts(&parts[0].1), vec!["aaaa", "bbbb"]);
+        assert_eq!(texts(&parts[1].1), vec!["cccc", "dddd"]);
+        assert_eq!(texts(&parts[2].1), vec!["eeee"]);
+    }
+
+    #[test]
+    fn split_keeps_overlong_line_in_its_own_part() {
+        let lines = display_lines(&["a", "bbbbbbbbbbbb", "c"], Some(1));
+        let parts = split(lines, 5, OutputFormat::Plain);
+        let ranges = parts
+            .iter()
+            .map(|(range, _)| range.clone())
+            .collect::<Vec<Range<usize>>>();
+        assert_eq!(ranges, vec![0..1, 1..2, 2..3]);
+        assert!(parts[1].1[0].is_event_line);
+    }
+
+    #[test]
+    fn keep_ends_keeps_more_of_the_start() {
+        let lines = display_lines(&["1", "2", "3", "4", "5"], None);
+        assert_eq!(
+            texts(&keep_ends(&lines, 3)),
+            vec!["1", "2", "… 2 lines omitted …", "5"]
+        );
+        assert_eq!(texts(&keep_ends(&lines, 0)), vec!["… 5 lines omitted …"]);
+        assert_eq!(texts(&keep_ends(&lines, 5)), vec!["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn elide_always_keeps_the_event_line() {
+        let lines = display_lines(&["b1", "b2", "EVENT", "a1"], Some(2));
+        let elided = elide(lines, 1, OutputFormat::Plain);
+        assert_eq!(
+            texts(&elided),
+            vec!["… 2 lines omitted …", "EVENT", "… 1 lines omitted …"]
+        );
+        assert!(elided[1].is_event_line);
+    }
+
+    /// A line of 30 characters, so leaving lines out saves more than the marker costs
+    fn long(label: &str) -> String {
+        format!("{:-<30}", label)
+    }
+
+    #[test]
+    fn elide_trims_the_longer_side_first() {
+        let texts_in = ["b1", "b2", "b3", "b4", "EVENT", "a1", "a2"].map(long);
+        let lines = display_lines(&texts_in.each_ref().map(String::as_str), Some(4));
+        // Lines are 31 characters with their newline and the marker is 20, so leaving out one
+        // line isn't enough, but two are
+        let max_chars = char_count(&lines, OutputFormat::Plain) - 30;
+        let elided = elide(lines, max_chars, OutputFormat::Plain);
+        assert!(char_count(&elided, OutputFormat::Plain) <= max_chars);
+        assert_eq!(
+            texts(&elided),
+            vec![
+                long("b1").as_str(),
+                "… 2 lines omitted …",
+                long("b4").as_str(),
+                long("EVENT").as_str(),
+                long("a1").as_str(),
+                long("a2").as_str(),
+            ]
+        );
+    }
+
+    #[test]
+    fn elide_without_event_line_keeps_ends() {
+        let texts_in = ["1", "2", "3", "4", "5", "6"].map(long);
+        let lines = display_lines(&texts_in.each_ref().map(String::as_str), None);
+        let elided = elide(lines, 100, OutputFormat::Plain);
+        assert!(char_count(&elided, OutputFormat::Plain) <= 100);
+        assert_eq!(
+            texts(&elided),
+            vec![
+                long("1").as_str(),
+                "… 4 lines omitted …",
+                long("6").as_str()
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_splits_into_numbered_parts() {
+        let lines = (0..6)
+            .map(|index| LogLine {
+                date: Utc::now(),
+                line: format!("line {}", index),
+                is_event_line: index == 0,
+            })
+            .collect::<Vec<LogLine>>();
+        let limits = MessageLimitsConfig {
+            max_chars: Some(120),
+            max_line_chars: None,
+            split: true,
+        };
+        let parts = layout(&lines, &limits, OutputFormat::Plain);
+        assert!(parts.len() > 1);
+        let mut covered = 0;
+        for (index, part) in parts.iter().enumerate() {
+            assert_eq!(part.number, index + 1);
+            assert_eq!(part.total, parts.len());
+            assert_eq!(part.lines.len(), part.display_lines.len());
+            covered += part.lines.len();
+        }
+        assert_eq!(covered, lines.len());
+    }
+
+    #[test]
+    fn layout_truncates_long_lines() {
+        let lines = vec![LogLine {
+            date: Utc::now(),
+            line: "x".repeat(200),
+            is_event_line: true,
+        }];
+        let limits = MessageLimitsConfig {
+            max_chars: None,
+            max_line_chars: Some(50),
+            split: false,
+        };
+        let parts = layout(&lines, &limits, OutputFormat::Markdown);
+        assert_eq!(parts.len(), 1);
+        let text = &parts[0].display_lines[0].text;
+        assert_eq!(text.chars().count(), 50);
+        assert!(text.ends_with('…'));
+    }
+
+    #[test]
+    fn sanitise_strips_escape_sequences_and_controls() {
+        assert_eq!(sanitise("\u{1B}[1;31mred\u{1B}[0m text"), "red text");
+        // Operating system commands, ended by BEL or ESC \
+        assert_eq!(sanitise("\u{1B}]0;title\u{7}after"), "after");
+        assert_eq!(sanitise("\u{1B}]8;;http://example.com\u{1B}\\link"), "link");
+        // Single character sequences and a trailing ESC
+        assert_eq!(sanitise("a\u{1B}Mb\u{1B}"), "ab");
+        // Tabs are kept, other control characters aren't
+        assert_eq!(sanitise("a\tb\r\u{0}c"), "a\tbc");
+        // An unterminated control sequence swallows the rest of the line
+        assert_eq!(sanitise("ok\u{1B}[123"), "ok");
+    }
+
+    #[test]
+    fn neutralise_mentions_breaks_mentions_but_not_emails() {
+        assert_eq!(neutralise_mentions("hi @channel"), "hi @\u{200B}channel");
+        assert_eq!(neutralise_mentions("<!here>"), "<\u{200B}!here>");
+        assert_eq!(neutralise_mentions("<@U123>"), "<\u{200B}@U123>");
+        assert_eq!(
+            neutralise_mentions("mail bob@example.com"),
+            "mail bob@example.com"
+        );
+    }
+
+    #[test]
+    fn markdown_block_cant_be_closed_early() {
+        let lines = display_lines(&["a ``` b"], None);
+        let block = markdown_block(&lines);
+        assert_eq!(block.matches("```").count(), 2);
+        assert!(block.starts_with("\n```\n") && block.ends_with("\n```\n"));
+    }
+
+    #[test]
+    fn html_block_escapes_lines() {
+        let lines = display_lines(&["<b>&", "event"], Some(1));
+        assert_eq!(
+            html_block(&lines),
+            "<pre>&lt;b&gt;&amp;\n<strong style=\"background-color: #fdd\">event</strong></pre>"
+        );
+    }
+
+    #[test]
+    fn measured_sizes_match_rendering() {
+        let lines = display_lines(&["a ``` b", "<@U123> & <b>", "EVENT", "", "tail"], Some(2));
+        for output_format in [
+            OutputFormat::Markdown,
+            OutputFormat::Plain,
+            OutputFormat::Html,
+        ] {
+            let measure = Measure::new(output_format);
+            for end in 0..=lines.len() {
+                let line_chars = lines[..end].iter().map(|line| measure.line(line)).sum();
+                assert_eq!(
+                    measure.block(line_chars, end),
+                    char_count(&lines[..end], output_format)
+                );
+            }
+            let run = RunChars::new(&lines, &measure);
+            for keep in 0..=lines.len() {
+                let kept = keep_ends(&lines, keep);
+                let (chars, count) = run.keep_ends(keep);
+                assert_eq!(count, kept.len());
+                assert_eq!(
+                    measure.block(chars, count),
+                    char_count(&kept, output_format)
+                );
+            }
+        }
+    }
+}
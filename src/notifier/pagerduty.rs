@@ -0,0 +1,413 @@
+use crate::config::{PagerDutyNotifierConfig, Severity};
+use crate::delivery;
+use crate::delivery::DeliveryError;
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::{BackEnd, EventContext, OpenIncidents};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// PagerDuty limits summaries to 1024 characters
+const MAX_SUMMARY_CHARS: usize = 1024;
+
+/// Body of a request to the Events API v2
+#[derive(Serialize)]
+struct PagerDutyEvent<'a> {
+    routing_key: &'a str,
+    event_action: PagerDutyAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<PagerDutyPayload>,
+    client: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PagerDutyAction {
+    Trigger,
+    Acknowledge,
+    Resolve,
+}
+
+#[derive(Serialize)]
+struct PagerDutyPayload {
+    summary: String,
+    source: String,
+    severity: Severity,
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    component: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    custom_details: Value,
+}
+
+/// Body of a request to the Change Events API, used for messages which shouldn't page anyone
+#[derive(Serialize)]
+struct PagerDutyChangeEvent<'a> {
+    routing_key: &'a str,
+    payload: PagerDutyChangePayload,
+}
+
+#[derive(Serialize)]
+struct PagerDutyChangePayload {
+    summary: String,
+    source: String,
+    timestamp: DateTime<Utc>,
+    custom_details: Value,
+}
+
+/// Triggers PagerDuty incidents for events, and resolves them once events stop
+pub struct PagerDutyBackEnd {
+    pub(crate) config: PagerDutyNotifierConfig,
+    pub(crate) client: reqwest::Client,
+    routing_key: String,
+    /// When each open incident, by dedup key, last had an event
+    open_incidents: Mutex<OpenIncidents>,
+}
+
+impl PagerDutyBackEnd {
+    /// Create the back-end, resolving the routing key from the environment or a file
+    pub(crate) fn new(config: PagerDutyNotifierConfig) -> Result<PagerDutyBackEnd, String> {
+        let routing_key = config
+            .routing_key
+            .resolve()
+            .map_err(|e| format!("routing_key: {}", e))?;
+        Ok(PagerDutyBackEnd {
+            config,
+            client: delivery::http_client(),
+            routing_key,
+            open_incidents: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Events from the same fileset and monitor, and with the same value for the dedup
+    /// capture if set, are grouped into one incident
+    fn dedup_key(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        captures: &HashMap<String, String>,
+    ) -> String {
+        let mut dedup_key = format!("{}/{}", fileset_id, monitor_id);
+        if let Some(capture) = &self.config.dedup_key_capture {
+            if let Some(value) = captures.get(capture) {
+                dedup_key = dedup_key + "/" + value;
+            }
+        }
+        dedup_key
+    }
+
+    fn to_json(&self, event: PagerDutyEvent) -> String {
+        serde_json::to_string(&event).expect("Failed to build JSON")
+    }
+
+    /// Record the incident opened by a trigger once it's been delivered, so that only
+    /// incidents PagerDuty knows about are resolved. This includes triggers delivered from the
+    /// outbox after their incident was resolved.
+    fn delivered(&self, payload: &str) {
+        let event: Value = match serde_json::from_str(payload) {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        if event["event_action"] != "trigger" {
+            return;
+        }
+        if let Some(dedup_key) = event["dedup_key"].as_str() {
+            self.open_incidents
+                .lock()
+                .expect("unpoisoned lock")
+                .insert(dedup_key.to_string(), Utc::now());
+        }
+    }
+}
+
+/// Truncate to the maximum summary length
+fn summary(text: &str) -> String {
+    text.chars().take(MAX_SUMMARY_CHARS).collect()
+}
+
+#[async_trait]
+impl BackEnd for PagerDutyBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        let dedup_key = self.dedup_key(
+            event_context.fileset_id,
+            event_context.monitor_id,
+            event_context.captures,
+        );
+        let default_summary = |context: &EventContext| {
+            format!(
+                "{}: {}",
+                context.title(),
+                context
+                    .event_line
+                    .map(|line| line.line.as_str())
+                    .unwrap_or_default()
+            )
+        };
+        let summary_text = match &self.config.summary_template {
+            Some(summary_template) => {
+                event_context.render_or_else(summary_template, default_summary)
+            }
+            None => default_summary(event_context),
+        };
+        self.to_json(PagerDutyEvent {
+            routing_key: &self.routing_key,
+            event_action: PagerDutyAction::Trigger,
+            dedup_key: Some(dedup_key),
+            payload: Some(PagerDutyPayload {
+                summary: summary(&summary_text),
+                source: event_context.hostname.to_string(),
+                severity: self
+                    .config
+                    .severity
+                    .or(event_context.severity)
+                    .unwrap_or(Severity::Error),
+                timestamp: event_context
+                    .event_line
+                    .map(|line| line.date)
+                    .unwrap_or_else(Utc::now),
+                component: Some(event_context.fileset_id.clone()),
+                group: Some(event_context.monitor_id.clone()),
+                custom_details: json!({
+                    "lines": event_context.lines.iter().map(|line| &line.line).collect::<Vec<_>>(),
+                    "source_file": event_context.source_file,
+                    "captures": event_context.captures,
+                    "counts": event_context.counts,
+                    "skipped": event_context.skipped,
+                }),
+            }),
+            client: "Centinela",
+        })
+    }
+
+    /// Messages such as digests, reports and the files last seen message are sent as change
+    /// events, which are recorded against the service without raising an incident
+    fn message_payload(&self, message: &str) -> String {
+        serde_json::to_string(&PagerDutyChangeEvent {
+            routing_key: &self.routing_key,
+            payload: PagerDutyChangePayload {
+                summary: summary(message.lines().next().unwrap_or_default()),
+                source: "centinela".to_string(),
+                timestamp: Utc::now(),
+                custom_details: json!({ "message": message }),
+            },
+        })
+        .expect("Failed to build JSON")
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        // Change events are the only payloads without an event action
+        let is_event = serde_json::from_str::<Value>(payload)
+            .is_ok_and(|payload| payload.get("event_action").is_some());
+        let url = if is_event {
+            &self.config.url
+        } else {
+            &self.config.change_url
+        };
+        let res = self
+            .client
+            .post(url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await;
+        delivery::check_response(res)?;
+        self.delivered(payload);
+        Ok(())
+    }
+
+    /// Keep an open incident from being resolved while its monitor is still firing, even if
+    /// the event isn't sent
+    fn event_seen(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        captures: &HashMap<String, String>,
+    ) {
+        let dedup_key = self.dedup_key(fileset_id, monitor_id, captures);
+        if let Some(last_seen) = self
+            .open_incidents
+            .lock()
+            .expect("unpoisoned lock")
+            .get_mut(&dedup_key)
+        {
+            *last_seen = Utc::now();
+        }
+    }
+
+    fn due_payloads(&self) -> Vec<String> {
+        let resolve_after = self
+            .config
+            .resolve_after
+            .and_then(|resolve_after| i64::try_from(resolve_after).ok())
+            .and_then(Duration::try_seconds);
+        let now = Utc::now();
+        let mut open_incidents = self.open_incidents.lock().expect("unpoisoned lock");
+        let cleared = open_incidents
+            .iter()
+            .filter(|(_, last_seen)| {
+                resolve_after
+                    .and_then(|resolve_after| now.checked_sub_signed(resolve_after))
+                    .is_some_and(|cutoff| cutoff >= **last_seen)
+            })
+            .map(|(dedup_key, _)| dedup_key.clone())
+            .collect::<Vec<String>>();
+        cleared
+            .into_iter()
+            .map(|dedup_key| {
+                open_incidents.remove(&dedup_key);
+                println!("Resolving PagerDuty incident {}", dedup_key);
+                self.to_json(PagerDutyEvent {
+                    routing_key: &self.routing_key,
+                    event_action: PagerDutyAction::Resolve,
+                    dedup_key: Some(dedup_key),
+                    payload: None,
+                    client: "Centinela",
+                })
+            })
+            .collect()
+    }
+
+    /// Acknowledge the incident for an alert acknowledged through the API, if it's open
+    fn acknowledge_payloads(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        captures: &HashMap<String, String>,
+    ) -> Vec<String> {
+        let dedup_key = self.dedup_key(fileset_id, monitor_id, captures);
+        if !self
+            .open_incidents
+            .lock()
+            .expect("unpoisoned lock")
+            .contains_key(&dedup_key)
+        {
+            return vec![];
+        }
+        println!("Acknowledging PagerDuty incident {}", dedup_key);
+        vec![self.to_json(PagerDutyEvent {
+            routing_key: &self.routing_key,
+            event_action: PagerDutyAction::Acknowledge,
+            dedup_key: Some(dedup_key),
+            payload: None,
+            client: "Centinela",
+        })]
+    }
+
+    fn open_incidents(&self) -> OpenIncidents {
+        self.open_incidents.lock().expect("unpoisoned lock").clone()
+    }
+
+    fn restore_open_incidents(&self, open_incidents: OpenIncidents) {
+        self.open_incidents
+            .lock()
+            .expect("unpoisoned lock")
+            .extend(open_incidents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn back_end(resolve_after: Option<usize>) -> PagerDutyBackEnd {
+        let mut config: PagerDutyNotifierConfig =
+            serde_json::from_value(json!({ "routing_key": { "value": "key" } })).unwrap();
+        config.resolve_after = resolve_after;
+        PagerDutyBackEnd::new(config).unwrap()
+    }
+
+    fn resolved_keys(payloads: Vec<String>) -> Vec<String> {
+        payloads
+            .iter()
+            .map(|payload| {
+                let payload: Value = serde_json::from_str(payload).unwrap();
+                assert_eq!(payload["event_action"], "resolve");
+                payload["dedup_key"].as_str().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn event_seen_keeps_open_incident_from_resolving() {
+        let back_end = back_end(Some(60));
+        let key = "web/errors".to_string();
+        let stale = Utc::now() - Duration::seconds(120);
+        back_end.restore_open_incidents(HashMap::from([(key.clone(), stale)]));
+        back_end.event_seen(&"web".to_string(), &"errors".to_string(), &HashMap::new());
+        assert!(back_end.due_payloads().is_empty());
+        assert!(back_end.open_incidents()[&key] > stale);
+    }
+
+    #[test]
+    fn event_seen_doesnt_open_incidents() {
+        let back_end = back_end(Some(60));
+        back_end.event_seen(&"web".to_string(), &"errors".to_string(), &HashMap::new());
+        assert!(back_end.open_incidents().is_empty());
+    }
+
+    #[test]
+    fn restored_incidents_are_resolved() {
+        let back_end = back_end(Some(60));
+        back_end.restore_open_incidents(HashMap::from([(
+            "web/errors".to_string(),
+            Utc::now() - Duration::seconds(120),
+        )]));
+        assert_eq!(resolved_keys(back_end.due_payloads()), vec!["web/errors"]);
+        assert!(back_end.open_incidents().is_empty());
+    }
+
+    #[test]
+    fn incidents_are_only_recorded_once_triggers_are_delivered() {
+        let back_end = back_end(Some(60));
+        let trigger = json!({ "event_action": "trigger", "dedup_key": "web/errors" }).to_string();
+        let resolve = json!({ "event_action": "resolve", "dedup_key": "db/slow" }).to_string();
+        back_end.delivered(&resolve);
+        assert!(back_end.open_incidents().is_empty());
+        back_end.delivered(&trigger);
+        assert!(back_end.open_incidents().contains_key("web/errors"));
+        // A trigger delivered from the outbox after its incident was resolved reopens it
+        back_end.restore_open_incidents(HashMap::from([(
+            "web/errors".to_string(),
+            Utc::now() - Duration::seconds(120),
+        )]));
+        assert_eq!(resolved_keys(back_end.due_payloads()), vec!["web/errors"]);
+        back_end.delivered(&trigger);
+        assert!(back_end.open_incidents().contains_key("web/errors"));
+    }
+
+    #[test]
+    fn messages_are_change_events() {
+        let back_end = back_end(None);
+        let message = back_end.message_payload("Files last seen\nweb: 5m ago");
+        back_end.delivered(&message);
+        assert!(back_end.open_incidents().is_empty());
+        let payload: Value = serde_json::from_str(&message).unwrap();
+        assert!(payload.get("event_action").is_none());
+        assert!(payload.get("dedup_key").is_none());
+        assert_eq!(payload["payload"]["summary"], "Files last seen");
+    }
+
+    #[test]
+    fn acknowledges_only_open_incidents() {
+        let back_end = back_end(None);
+        let (fileset_id, monitor_id) = ("web".to_string(), "errors".to_string());
+        assert!(back_end
+            .acknowledge_payloads(&fileset_id, &monitor_id, &HashMap::new())
+            .is_empty());
+        back_end.restore_open_incidents(HashMap::from([("web/errors".to_string(), Utc::now())]));
+        let payloads = back_end.acknowledge_payloads(&fileset_id, &monitor_id, &HashMap::new());
+        assert_eq!(payloads.len(), 1);
+        let payload: Value = serde_json::from_str(&payloads[0]).unwrap();
+        assert_eq!(payload["event_action"], "acknowledge");
+        assert_eq!(payload["dedup_key"], "web/errors");
+        // Acknowledged incidents are still resolved later
+        assert!(back_end.open_incidents().contains_key("web/errors"));
+    }
+}
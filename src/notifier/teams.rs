@@ -0,0 +1,122 @@
+use crate::config::{MessageLimitsConfig, TeamsNotifierConfig};
+use crate::delivery;
+use crate::delivery::DeliveryError;
+use crate::notifier::{BackEnd, EventContext};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+/// Microsoft Teams incoming webhook, sent Adaptive Cards
+pub struct TeamsBackEnd {
+    pub(crate) config: TeamsNotifierConfig,
+    pub(crate) client: reqwest::Client,
+}
+
+impl TeamsBackEnd {
+    pub(crate) fn new(config: TeamsNotifierConfig) -> TeamsBackEnd {
+        TeamsBackEnd {
+            config,
+            client: delivery::http_client(),
+        }
+    }
+}
+
+/// Wrap card body elements in the message envelope Teams expects
+fn card_message(body: Vec<Value>) -> String {
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": body,
+            }
+        }]
+    })
+    .to_string()
+}
+
+#[async_trait]
+impl BackEnd for TeamsBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        let mut body = vec![json!({
+            "type": "TextBlock",
+            "text": event_context.title(),
+            "size": "Medium",
+            "weight": "Bolder",
+            "wrap": true,
+        })];
+        match &self.config.body_template {
+            Some(body_template) => body.push(json!({
+                "type": "TextBlock",
+                "text": event_context.render_or_else(body_template, EventContext::default_text),
+                "wrap": true,
+            })),
+            // One block per line so the event line can be highlighted
+            None => body.push(json!({
+                "type": "Container",
+                "style": "emphasis",
+                "items": event_context
+                    .display_lines
+                    .iter()
+                    .map(|line| {
+                        json!({
+                            "type": "TextBlock",
+                            "text": line.text,
+                            "fontType": "Monospace",
+                            "wrap": true,
+                            "spacing": "None",
+                            "weight": if line.is_event_line { "Bolder" } else { "Default" },
+                            "color": if line.is_event_line { "Attention" } else { "Default" },
+                        })
+                    })
+                    .collect::<Vec<Value>>(),
+            })),
+        }
+        let mut facts = vec![
+            json!({ "title": "Source file", "value": event_context.source_file }),
+            json!({ "title": "Last hour", "value": event_context.counts.last_hour.to_string() }),
+        ];
+        if event_context.skipped > 0 {
+            facts.push(json!({ "title": "Skipped", "value": event_context.skipped.to_string() }));
+        }
+        if event_context.parts > 1 {
+            facts.push(json!({
+                "title": "Part",
+                "value": format!("{} of {}", event_context.part, event_context.parts),
+            }));
+        }
+        body.push(json!({ "type": "FactSet", "facts": facts }));
+        card_message(body)
+    }
+
+    fn message_payload(&self, message: &str) -> String {
+        card_message(vec![json!({
+            "type": "TextBlock",
+            "text": message,
+            "wrap": true,
+        })])
+    }
+
+    /// Teams rejects cards over about 28KB
+    fn default_limits(&self) -> MessageLimitsConfig {
+        MessageLimitsConfig {
+            max_chars: Some(20000),
+            max_line_chars: Some(2000),
+            split: false,
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let res = self
+            .client
+            .post(self.config.url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await;
+        delivery::check_response(res)
+    }
+}
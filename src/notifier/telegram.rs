@@ -0,0 +1,245 @@
+use crate::config::{MessageLimitsConfig, TelegramNotifierConfig, TelegramParseMode};
+use crate::data::escape_html;
+use crate::delivery;
+use crate::delivery::DeliveryError;
+use crate::notifier::{truncate_chars, BackEnd, EventContext};
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Telegram limits messages to 4096 characters
+const MAX_TEXT_CHARS: usize = 4096;
+/// Leaves room for the title and formatting around the event lines
+const MAX_LINES_CHARS: usize = 3500;
+
+/// Sends messages to a chat via a Telegram bot
+pub struct TelegramBackEnd {
+    pub(crate) config: TelegramNotifierConfig,
+    pub(crate) client: reqwest::Client,
+    send_message_url: String,
+}
+
+impl TelegramBackEnd {
+    /// Create the back-end, resolving the bot token from the environment or a file
+    pub(crate) fn new(config: TelegramNotifierConfig) -> Result<TelegramBackEnd, String> {
+        let bot_token = config
+            .bot_token
+            .resolve()
+            .map_err(|e| format!("bot_token: {}", e))?;
+        let send_message_url = format!(
+            "{}/bot{}/sendMessage",
+            config.api_url.trim_end_matches('/'),
+            bot_token
+        );
+        Ok(TelegramBackEnd {
+            config,
+            client: delivery::http_client(),
+            send_message_url,
+        })
+    }
+
+    /// The event title and lines, formatted for the configured parse mode. The lines are
+    /// shortened before they're escaped so the text fits without cutting an entity in two.
+    fn default_text(&self, event_context: &EventContext) -> String {
+        let lines = event_context
+            .display_lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let skipped = match event_context.skipped {
+            0 => "".to_string(),
+            skipped => format!(
+                "\n({} notifications skipped due to high frequency)",
+                skipped
+            ),
+        } + &event_context.part_text();
+        let title = event_context.title();
+        let overhead = self.format_text(&title, "", &skipped).chars().count();
+        let lines = fit_lines(
+            self.config.parse_mode,
+            &lines,
+            MAX_TEXT_CHARS.saturating_sub(overhead),
+        );
+        self.format_text(&title, &lines, &skipped)
+    }
+
+    /// Put the title, lines and skipped note together, escaping each for the parse mode
+    fn format_text(&self, title: &str, lines: &str, skipped: &str) -> String {
+        match self.config.parse_mode {
+            TelegramParseMode::Html => format!(
+                "<b>{}</b>\n<pre>{}</pre>{}",
+                escape_html(title),
+                escape_lines(self.config.parse_mode, lines),
+                escape_html(skipped)
+            ),
+            TelegramParseMode::Markdown => format!(
+                "*{}*\n```\n{}\n```{}",
+                escape_markdown(title),
+                escape_lines(self.config.parse_mode, lines),
+                escape_markdown(skipped)
+            ),
+            TelegramParseMode::MarkdownV2 => format!(
+                "*{}*\n```\n{}\n```{}",
+                escape_markdown_v2(title),
+                escape_lines(self.config.parse_mode, lines),
+                escape_markdown_v2(skipped)
+            ),
+            TelegramParseMode::None => format!("{}\n{}{}", title, lines, skipped),
+        }
+    }
+}
+
+/// Escape event lines for the code block of the parse mode
+fn escape_lines(parse_mode: TelegramParseMode, lines: &str) -> String {
+    match parse_mode {
+        TelegramParseMode::Html => escape_html(lines),
+        // Legacy markdown can't escape inside a code block, so backticks are swapped
+        TelegramParseMode::Markdown => lines.replace('`', "'"),
+        TelegramParseMode::MarkdownV2 => lines.replace('\\', "\\\\").replace('`', "\\`"),
+        TelegramParseMode::None => lines.to_string(),
+    }
+}
+
+/// The longest start of the raw lines which fits in max_chars once escaped, marking where
+/// it was cut
+fn fit_lines(parse_mode: TelegramParseMode, lines: &str, max_chars: usize) -> String {
+    if escape_lines(parse_mode, lines).chars().count() <= max_chars {
+        return lines.to_string();
+    }
+    let marker = "\n…";
+    let mut budget = max_chars.saturating_sub(marker.chars().count());
+    let mut fitted = String::new();
+    for c in lines.chars() {
+        let escaped_chars = escape_lines(parse_mode, c.encode_utf8(&mut [0; 4]))
+            .chars()
+            .count();
+        if escaped_chars > budget {
+            break;
+        }
+        budget -= escaped_chars;
+        fitted.push(c);
+    }
+    fitted + marker
+}
+
+/// Escape characters which are special outside entities in legacy Markdown text
+fn escape_markdown(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        if "_*`[".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// Escape characters which are special anywhere in MarkdownV2 text
+fn escape_markdown_v2(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+#[async_trait]
+impl BackEnd for TelegramBackEnd {
+    fn event_payload(&self, event_context: &EventContext) -> String {
+        // The default text is already sized to fit. Rendered templates are the user's to
+        // keep within the limit, and are cut if they don't.
+        let text = match &self.config.body_template {
+            Some(body_template) => truncate_chars(
+                &event_context.render_or_else(body_template, |context| self.default_text(context)),
+                MAX_TEXT_CHARS,
+            ),
+            None => self.default_text(event_context),
+        };
+        let mut body = json!({
+            "chat_id": self.config.chat_id,
+            "text": text,
+        });
+        if self.config.parse_mode != TelegramParseMode::None {
+            body["parse_mode"] = json!(self.config.parse_mode);
+        }
+        body.to_string()
+    }
+
+    /// Messages are sent as plain text, since they may contain anything
+    fn message_payload(&self, message: &str) -> String {
+        json!({
+            "chat_id": self.config.chat_id,
+            "text": truncate_chars(message, MAX_TEXT_CHARS),
+        })
+        .to_string()
+    }
+
+    fn default_limits(&self) -> MessageLimitsConfig {
+        MessageLimitsConfig {
+            max_chars: Some(MAX_LINES_CHARS),
+            max_line_chars: Some(1000),
+            split: false,
+        }
+    }
+
+    async fn deliver(&self, payload: &str) -> Result<(), DeliveryError> {
+        let res = self
+            .client
+            .post(self.send_message_url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await;
+        delivery::check_response(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_markdown_escapes_entity_characters() {
+        assert_eq!(
+            escape_markdown("db_timeout *x* `y` [z]"),
+            r"db\_timeout \*x\* \`y\` \[z]"
+        );
+    }
+
+    #[test]
+    fn escape_markdown_v2_escapes_reserved_characters() {
+        assert_eq!(escape_markdown_v2("a.b_c(1)!"), r"a\.b\_c\(1\)\!");
+    }
+
+    #[test]
+    fn escape_lines_keeps_code_blocks_closed() {
+        assert_eq!(
+            escape_lines(TelegramParseMode::Markdown, "a ```b"),
+            "a '''b"
+        );
+        assert_eq!(
+            escape_lines(TelegramParseMode::MarkdownV2, r"a`\"),
+            r"a\`\\"
+        );
+        assert_eq!(
+            escape_lines(TelegramParseMode::Html, "<a&b>"),
+            "&lt;a&amp;b&gt;"
+        );
+    }
+
+    #[test]
+    fn fit_lines_leaves_short_lines_alone() {
+        assert_eq!(fit_lines(TelegramParseMode::Html, "<b>", 100), "<b>");
+    }
+
+    #[test]
+    fn fit_lines_cuts_before_escaping() {
+        // Each "<" becomes "&lt;", so only whole escapes fit
+        let fitted = fit_lines(TelegramParseMode::Html, "<<<<<<<<", 12);
+        assert_eq!(fitted, "<<\n…");
+        let escaped = escape_lines(TelegramParseMode::Html, &fitted);
+        assert_eq!(escaped, "&lt;&lt;\n…");
+        assert!(escaped.chars().count() <= 12);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::data::{EventCounts, FileSetData};
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use chrono::{DateTime, Datelike, Duration, Months, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Most points a query may return across all its series, to bound response size
+const MAX_POINTS: usize = 100_000;
+
+/// Resolution of a count series. Each reads the EventCounts buckets of the same size.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Step {
+    Second,
+    #[default]
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Step {
+    fn buckets<'a>(&self, counts: &'a EventCounts) -> &'a HashMap<DateTime<Utc>, usize> {
+        match self {
+            Step::Second => &counts.seconds,
+            Step::Minute => &counts.minutes,
+            Step::Hour => &counts.hours,
+            Step::Day => &counts.days,
+            Step::Week => &counts.weeks,
+            Step::Month => &counts.months,
+            Step::Year => &counts.years,
+        }
+    }
+
+    /// Start of the bucket containing a moment, matching how EventCounts keys its buckets
+    fn start_of(&self, moment: DateTime<Utc>) -> DateTime<Utc> {
+        let start = match self {
+            Step::Second => Utc.with_ymd_and_hms(
+                moment.year(),
+                moment.month(),
+                moment.day(),
+                moment.hour(),
+                moment.minute(),
+                moment.second(),
+            ),
+            Step::Minute => Utc.with_ymd_and_hms(
+                moment.year(),
+                moment.month(),
+                moment.day(),
+                moment.hour(),
+                moment.minute(),
+                0,
+            ),
+            Step::Hour => Utc.with_ymd_and_hms(
+                moment.year(),
+                moment.month(),
+                moment.day(),
+                moment.hour(),
+                0,
+                0,
+            ),
+            Step::Day | Step::Week => {
+                Utc.with_ymd_and_hms(moment.year(), moment.month(), moment.day(), 0, 0, 0)
+            }
+            Step::Month => Utc.with_ymd_and_hms(moment.year(), moment.month(), 1, 0, 0, 0),
+            Step::Year => Utc.with_ymd_and_hms(moment.year(), 1, 1, 0, 0, 0),
+        }
+        .unwrap();
+        match self {
+            // Weeks start on Monday
+            Step::Week => start - Duration::days(moment.weekday().num_days_from_monday() as i64),
+            _ => start,
+        }
+    }
+
+    /// A bucket start moved on by a number of steps, which may be negative
+    fn offset(&self, start: DateTime<Utc>, steps: i32) -> Option<DateTime<Utc>> {
+        let months = |months: i32| match months >= 0 {
+            true => start.checked_add_months(Months::new(months as u32)),
+            false => start.checked_sub_months(Months::new(months.unsigned_abs())),
+        };
+        match self {
+            Step::Second => start.checked_add_signed(Duration::seconds(steps as i64)),
+            Step::Minute => start.checked_add_signed(Duration::minutes(steps as i64)),
+            Step::Hour => start.checked_add_signed(Duration::hours(steps as i64)),
+            Step::Day => start.checked_add_signed(Duration::days(steps as i64)),
+            Step::Week => start.checked_add_signed(Duration::weeks(steps as i64)),
+            Step::Month => months(steps),
+            Step::Year => months(steps.saturating_mul(12)),
+        }
+    }
+}
+
+/// How series for several filesets and monitors are combined
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Aggregation {
+    /// A series for each monitor on each fileset
+    #[default]
+    None,
+    /// A series for each fileset, summed across its monitors
+    Monitors,
+    /// A series for each monitor, summed across filesets
+    Filesets,
+    /// A single series summed across everything
+    All,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Query string for GET /counts
+#[derive(Deserialize)]
+pub(crate) struct CountsQuery {
+    /// Start of the range. Defaults to give 60 steps ending with `to`.
+    pub(crate) from: Option<DateTime<Utc>>,
+    /// End of the range, inclusive. Defaults to now.
+    pub(crate) to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) step: Step,
+    /// Only count this fileset
+    pub(crate) fileset: Option<FileSetId>,
+    /// Only count this monitor
+    pub(crate) monitor: Option<MonitorId>,
+    #[serde(default)]
+    pub(crate) aggregate: Aggregation,
+    #[serde(default)]
+    pub(crate) format: Format,
+}
+
+/// Count for one step
+#[derive(Serialize)]
+pub(crate) struct Point {
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) count: usize,
+}
+
+/// Counts for a fileset and monitor, either of which is None if aggregated away
+#[derive(Serialize)]
+pub(crate) struct Series {
+    pub(crate) fileset: Option<FileSetId>,
+    pub(crate) monitor: Option<MonitorId>,
+    pub(crate) total: usize,
+    pub(crate) points: Vec<Point>,
+}
+
+/// The result of a count query
+#[derive(Serialize)]
+pub(crate) struct CountsResult {
+    pub(crate) from: DateTime<Utc>,
+    pub(crate) to: DateTime<Utc>,
+    pub(crate) step: Step,
+    pub(crate) aggregate: Aggregation,
+    pub(crate) series: Vec<Series>,
+}
+
+impl CountsResult {
+    /// One row per point, with empty fileset or monitor columns where aggregated away
+    pub(crate) fn to_csv(&self) -> String {
+        let mut csv = "time,fileset,monitor,count\n".to_string();
+        for series in &self.series {
+            for point in &series.points {
+                csv += &format!(
+                    "{},{},{},{}\n",
+                    point.time.to_rfc3339(),
+                    csv_field(series.fileset.as_deref().unwrap_or("")),
+                    csv_field(series.monitor.as_deref().unwrap_or("")),
+                    point.count
+                );
+            }
+        }
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Build zero-filled series of counts for the query, sorted by fileset, monitor and time.
+/// Steps older than the buckets kept for that step size count as zero.
+pub(crate) fn counts(
+    filesets_data: &HashMap<FileSetId, FileSetData>,
+    query: &CountsQuery,
+) -> Result<CountsResult, String> {
+    let step = query.step;
+    let to = step.start_of(query.to.unwrap_or_else(Utc::now));
+    let from = match query.from {
+        Some(from) => step.start_of(from),
+        None => step
+            .offset(to, -59)
+            .ok_or_else(|| "from is out of range".to_string())?,
+    };
+    if from > to {
+        return Err("from must not be after to".to_string());
+    }
+
+    // The start of each step in the range
+    let mut times = vec![];
+    let mut time = from;
+    while time <= to {
+        times.push(time);
+        if times.len() > MAX_POINTS {
+            return Err(format!("range has more than {} steps", MAX_POINTS));
+        }
+        time = match step.offset(from, times.len() as i32) {
+            Some(time) => time,
+            None => break,
+        };
+    }
+
+    // Sum the buckets of each fileset and monitor into their series
+    let mut totals: BTreeMap<(Option<&FileSetId>, Option<&MonitorId>), Vec<usize>> =
+        BTreeMap::new();
+    for (fileset_id, fileset_data) in filesets_data {
+        if query.fileset.as_ref().is_some_and(|id| id != fileset_id) {
+            continue;
+        }
+        for (monitor_id, monitor_data) in &fileset_data.monitor_data {
+            if query.monitor.as_ref().is_some_and(|id| id != monitor_id) {
+                continue;
+            }
+            let key = match query.aggregate {
+                Aggregation::None => (Some(fileset_id), Some(monitor_id)),
+                Aggregation::Monitors => (Some(fileset_id), None),
+                Aggregation::Filesets => (None, Some(monitor_id)),
+                Aggregation::All => (None, None),
+            };
+            let series_counts = totals.entry(key).or_insert_with(|| vec![0; times.len()]);
+            let buckets = step.buckets(&monitor_data.counts);
+            for (count, time) in series_counts.iter_mut().zip(&times) {
+                *count += buckets.get(time).copied().unwrap_or(0);
+            }
+        }
+    }
+    if totals.len().saturating_mul(times.len()) > MAX_POINTS {
+        return Err(format!(
+            "query returns more than {} points; narrow the range or aggregate",
+            MAX_POINTS
+        ));
+    }
+
+    let series = totals
+        .into_iter()
+        .map(|((fileset_id, monitor_id), series_counts)| Series {
+            fileset: fileset_id.cloned(),
+            monitor: monitor_id.cloned(),
+            total: series_counts.iter().sum(),
+            points: times
+                .iter()
+                .zip(series_counts)
+                .map(|(time, count)| Point { time: *time, count })
+                .collect(),
+        })
+        .collect();
+    Ok(CountsResult {
+        from,
+        to,
+        step,
+        aggregate: query.aggregate,
+        series,
+    })
+}
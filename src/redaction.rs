@@ -0,0 +1,195 @@
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+/// Default text which replaces redacted values
+const REDACTED: &str = "[REDACTED]";
+
+/// A redaction rule, either one of the built-in rules or a custom regex
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum RedactionConfig {
+    BuiltIn {
+        builtin: BuiltInRedaction,
+    },
+    Custom {
+        #[serde(with = "serde_regex")]
+        regex: Regex,
+        /// Replacement text, which may refer to capture groups as $1 or $name
+        #[serde(default = "default_replacement")]
+        replacement: String,
+    },
+}
+
+fn default_replacement() -> String {
+    REDACTED.to_string()
+}
+
+/// Built-in rules for common secret shapes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltInRedaction {
+    /// Email addresses
+    Email,
+    /// Payment card numbers which pass the Luhn check
+    CreditCard,
+    /// Authorization: Bearer tokens
+    BearerToken,
+    /// JSON Web Tokens
+    Jwt,
+    /// AWS access key IDs
+    AwsAccessKey,
+    /// Values assigned to keys such as password, secret, token or api_key, including
+    /// prefixed keys such as db_password
+    Password,
+}
+
+impl BuiltInRedaction {
+    /// The rule's regex and replacement
+    fn rule(&self) -> (&'static str, &'static str) {
+        match self {
+            BuiltInRedaction::Email => (r"[\w.+-]+@[\w-]+(\.[\w-]+)+", REDACTED),
+            BuiltInRedaction::CreditCard => (r"\b\d(?:[ -]?\d){12,18}\b", REDACTED),
+            BuiltInRedaction::BearerToken => (r"(?i)(bearer\s+)[\w\-.~+/]+=*", "${1}[REDACTED]"),
+            BuiltInRedaction::Jwt => (r"\beyJ[\w-]+\.[\w-]+\.[\w-]+", REDACTED),
+            BuiltInRedaction::AwsAccessKey => (r"\b(AKIA|ASIA)[0-9A-Z]{16}\b", REDACTED),
+            BuiltInRedaction::Password => (
+                // Keys may have a prefix, e.g. db_password or client_secret
+                r#"(?i)([\w-]*(?:password|passwd|pwd|secret|token|api[_-]?key))(["']?\s*[:=]\s*["']?)[^\s"',;&]+"#,
+                "${1}${2}[REDACTED]",
+            ),
+        }
+    }
+}
+
+/// A compiled redaction rule
+struct Rule {
+    regex: Regex,
+    replacement: String,
+    /// Only card numbers with a valid check digit are redacted
+    luhn: bool,
+}
+
+/// Applies a file set's redaction rules to lines as they're read
+pub(crate) struct Redactor {
+    rules: Vec<Rule>,
+}
+
+impl Redactor {
+    pub(crate) fn new_from_config(configs: &[RedactionConfig]) -> Redactor {
+        let rules = configs
+            .iter()
+            .map(|config| match config {
+                RedactionConfig::BuiltIn { builtin } => {
+                    let (regex, replacement) = builtin.rule();
+                    Rule {
+                        regex: Regex::new(regex).expect("Valid built-in redaction regex"),
+                        replacement: replacement.to_string(),
+                        luhn: matches!(builtin, BuiltInRedaction::CreditCard),
+                    }
+                }
+                RedactionConfig::Custom { regex, replacement } => Rule {
+                    regex: regex.clone(),
+                    replacement: replacement.clone(),
+                    luhn: false,
+                },
+            })
+            .collect();
+        Redactor { rules }
+    }
+
+    /// Apply all rules to a line, returning the redacted line and the number of redactions made
+    pub(crate) fn redact(&self, line: &str) -> (String, usize) {
+        let mut redactions = 0;
+        let mut redacted = line.to_string();
+        for rule in &self.rules {
+            redacted = rule
+                .regex
+                .replace_all(&redacted, |captures: &Captures| {
+                    if rule.luhn && !passes_luhn(&captures[0]) {
+                        return captures[0].to_string();
+                    }
+                    redactions += 1;
+                    let mut replacement = String::new();
+                    captures.expand(&rule.replacement, &mut replacement);
+                    replacement
+                })
+                .into_owned();
+        }
+        (redacted, redactions)
+    }
+}
+
+/// Check the Luhn check digit of a number, ignoring separators
+fn passes_luhn(number: &str) -> bool {
+    let digits = number
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .collect::<Vec<u32>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| match index % 2 {
+            0 => *digit,
+            _ if *digit * 2 > 9 => *digit * 2 - 9,
+            _ => *digit * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(builtin: BuiltInRedaction) -> Redactor {
+        Redactor::new_from_config(&[RedactionConfig::BuiltIn { builtin }])
+    }
+
+    #[test]
+    fn password_redacts_plain_keys() {
+        let redactor = redactor(BuiltInRedaction::Password);
+        assert_eq!(
+            redactor.redact("login password=hunter2 ok"),
+            ("login password=[REDACTED] ok".to_string(), 1)
+        );
+        assert_eq!(
+            redactor.redact(r#"{"api_key": "abc123"}"#),
+            (r#"{"api_key": "[REDACTED]"}"#.to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn password_redacts_prefixed_keys() {
+        let redactor = redactor(BuiltInRedaction::Password);
+        assert_eq!(
+            redactor.redact("db_password=hunter2 x_api_key=abc client_secret: s3cr3t"),
+            (
+                "db_password=[REDACTED] x_api_key=[REDACTED] client_secret: [REDACTED]".to_string(),
+                3
+            )
+        );
+        assert_eq!(
+            redactor.redact("X-Auth-Token: abc.def"),
+            ("X-Auth-Token: [REDACTED]".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn password_ignores_other_keys() {
+        let redactor = redactor(BuiltInRedaction::Password);
+        assert_eq!(
+            redactor.redact("password_hash=abc user=bob"),
+            ("password_hash=abc user=bob".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn credit_card_requires_luhn() {
+        let redactor = redactor(BuiltInRedaction::CreditCard);
+        assert_eq!(
+            redactor.redact("card 4111 1111 1111 1111 order 1234567890123"),
+            ("card [REDACTED] order 1234567890123".to_string(), 1)
+        );
+    }
+}
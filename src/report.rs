@@ -0,0 +1,321 @@
+use crate::data::{escape_html, FileSetData};
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::NotifierId;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Newtype
+pub(crate) type ReportId = String;
+
+/// A scheduled report summarising event counts for a period
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReportConfig {
+    /// Cron expression (sec min hour day-of-month month day-of-week [year]) for when the
+    /// report is sent
+    pub cron: cron::Schedule,
+    /// Timezone in which the cron expression is evaluated and times are shown. Defaults to UTC.
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Length in seconds of the period covered by the report, ending when it's sent
+    #[serde(default = "default_period")]
+    pub period: usize,
+    pub notifiers: Vec<NotifierId>,
+    /// File sets to include. All of them if not set.
+    pub filesets: Option<Vec<FileSetId>>,
+    #[serde(default)]
+    pub format: ReportFormat,
+    /// Number of the most frequent capture values to show for each monitor
+    #[serde(default = "default_top_values")]
+    pub top_values: usize,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_period() -> usize {
+    60 * 60 * 24
+}
+
+fn default_top_values() -> usize {
+    5
+}
+
+/// How a report is presented
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    /// A markdown table
+    #[default]
+    Markdown,
+    /// An HTML table for notifiers which support it, such as email, with the markdown
+    /// table as the plain text alternative
+    Html,
+}
+
+/// A report ready to send
+pub(crate) struct ReportMessage {
+    pub(crate) title: String,
+    pub(crate) text: String,
+    pub(crate) html: Option<String>,
+}
+
+/// One row of a report
+struct ReportRow {
+    fileset_id: FileSetId,
+    monitor_id: MonitorId,
+    events: usize,
+    previous_events: usize,
+    alerts: usize,
+    top_values: Vec<(String, usize)>,
+}
+
+impl ReportRow {
+    /// Change in events versus the previous period
+    fn change(&self) -> String {
+        let difference = self.events as i64 - self.previous_events as i64;
+        match self.previous_events {
+            0 if difference == 0 => "0".to_string(),
+            0 => format!("{:+}", difference),
+            previous => format!(
+                "{:+} ({:+.0}%)",
+                difference,
+                difference as f64 * 100.0 / previous as f64
+            ),
+        }
+    }
+
+    fn top_values_text(&self) -> String {
+        self.top_values
+            .iter()
+            .map(|(value, count)| format!("{} ({})", value, count))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// A configured report and when it's next due
+struct Report {
+    report_id: ReportId,
+    config: ReportConfig,
+    next_run: Option<DateTime<Utc>>,
+}
+
+/// Scheduled reports, and a log of the alerts sent so they can be reported on
+#[derive(Default)]
+pub(crate) struct Reports {
+    reports: Vec<Report>,
+    /// When each event notification was sent, by fileset and monitor
+    alerts: Vec<(DateTime<Utc>, FileSetId, MonitorId)>,
+}
+
+impl Reports {
+    pub(crate) fn new(configs: HashMap<ReportId, ReportConfig>) -> Reports {
+        let mut reports = configs
+            .into_iter()
+            .map(|(report_id, config)| Report {
+                next_run: next_run(&config),
+                report_id,
+                config,
+            })
+            .collect::<Vec<Report>>();
+        reports.sort_by(|a, b| a.report_id.cmp(&b.report_id));
+        Reports {
+            reports,
+            alerts: vec![],
+        }
+    }
+
+    /// Check that all notifier IDs referenced by reports exist
+    pub(crate) fn validate<T>(&self, notifiers: &HashMap<NotifierId, T>) {
+        for report in &self.reports {
+            for notifier_id in &report.config.notifiers {
+                if !notifiers.contains_key(notifier_id) {
+                    panic!(
+                        "Invalid notifier ID {:?} in config for report {:?}",
+                        notifier_id, report.report_id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Record an event notification being sent
+    pub(crate) fn record_alert(&mut self, fileset_id: &FileSetId, monitor_id: &MonitorId) {
+        if self.reports.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        self.alerts
+            .push((now, fileset_id.clone(), monitor_id.clone()));
+        // Keep enough for the longest report's current and previous periods
+        let longest = self
+            .reports
+            .iter()
+            .map(|report| report.config.period)
+            .max()
+            .unwrap_or(0);
+        let keep_since = now - Duration::seconds(2 * longest as i64);
+        self.alerts.retain(|(at, _, _)| *at >= keep_since);
+    }
+
+    /// Build any reports which are due, with the notifiers to send each to
+    pub(crate) fn take_due(
+        &mut self,
+        filesets_data: &HashMap<FileSetId, FileSetData>,
+        hostname: &str,
+    ) -> Vec<(Vec<NotifierId>, ReportMessage)> {
+        let now = Utc::now();
+        let mut due = vec![];
+        for report in &mut self.reports {
+            if report.next_run.is_none_or(|next_run| next_run > now) {
+                continue;
+            }
+            report.next_run = next_run(&report.config);
+            let message = build_report(report, filesets_data, &self.alerts, hostname, now);
+            due.push((report.config.notifiers.clone(), message));
+        }
+        due
+    }
+}
+
+/// The next time a report's cron expression fires
+fn next_run(config: &ReportConfig) -> Option<DateTime<Utc>> {
+    config
+        .cron
+        .upcoming(config.timezone)
+        .next()
+        .map(|next_run| next_run.with_timezone(&Utc))
+}
+
+/// Summarise each monitor on each included fileset for the period ending now
+fn build_report(
+    report: &Report,
+    filesets_data: &HashMap<FileSetId, FileSetData>,
+    alerts: &[(DateTime<Utc>, FileSetId, MonitorId)],
+    hostname: &str,
+    now: DateTime<Utc>,
+) -> ReportMessage {
+    let config = &report.config;
+    let period = Duration::seconds(config.period as i64);
+    let from = now - period;
+    let previous_from = from - period;
+    let mut rows = vec![];
+    for (fileset_id, fileset_data) in filesets_data {
+        if config
+            .filesets
+            .as_ref()
+            .is_some_and(|filesets| !filesets.contains(fileset_id))
+        {
+            continue;
+        }
+        for (monitor_id, monitor_data) in &fileset_data.monitor_data {
+            rows.push(ReportRow {
+                fileset_id: fileset_id.clone(),
+                monitor_id: monitor_id.clone(),
+                events: monitor_data.counts.total_between(from, now),
+                previous_events: monitor_data.counts.total_between(previous_from, from),
+                alerts: alerts
+                    .iter()
+                    .filter(|(at, alert_fileset_id, alert_monitor_id)| {
+                        *at >= from
+                            && alert_fileset_id == fileset_id
+                            && alert_monitor_id == monitor_id
+                    })
+                    .count(),
+                top_values: monitor_data
+                    .counts
+                    .top_values_between(from, now, config.top_values),
+            });
+        }
+    }
+    rows.sort_by(|a, b| (&a.fileset_id, &a.monitor_id).cmp(&(&b.fileset_id, &b.monitor_id)));
+
+    let time_format = "%Y-%m-%d %H:%M %Z";
+    let title = format!(
+        "Centinela {} report for {}: {} to {}",
+        report.report_id,
+        hostname,
+        from.with_timezone(&config.timezone).format(time_format),
+        now.with_timezone(&config.timezone).format(time_format)
+    );
+    let text = format!("**{}**\n\n{}", title, markdown_table(&rows));
+    let html = match config.format {
+        ReportFormat::Markdown => None,
+        ReportFormat::Html => Some(format!(
+            "<h2>{}</h2>\n{}",
+            escape_html(&title),
+            html_table(&rows)
+        )),
+    };
+    ReportMessage { title, text, html }
+}
+
+const HEADINGS: [&str; 7] = [
+    "File set",
+    "Monitor",
+    "Events",
+    "Previous period",
+    "Change",
+    "Alerts",
+    "Top values",
+];
+
+fn row_cells(row: &ReportRow) -> [String; 7] {
+    [
+        row.fileset_id.clone(),
+        row.monitor_id.clone(),
+        row.events.to_string(),
+        row.previous_events.to_string(),
+        row.change(),
+        row.alerts.to_string(),
+        row.top_values_text(),
+    ]
+}
+
+fn markdown_table(rows: &[ReportRow]) -> String {
+    if rows.is_empty() {
+        return "No monitors to report on.\n".to_string();
+    }
+    let escape = |cell: &str| cell.replace('|', "\\|");
+    let mut table = format!(
+        "| {} |\n|{}\n",
+        HEADINGS.join(" | "),
+        "---|".repeat(HEADINGS.len())
+    );
+    for row in rows {
+        table += &format!(
+            "| {} |\n",
+            row_cells(row)
+                .iter()
+                .map(|cell| escape(cell))
+                .collect::<Vec<String>>()
+                .join(" | ")
+        );
+    }
+    table
+}
+
+fn html_table(rows: &[ReportRow]) -> String {
+    let cells = |cells: &[String], tag: &str| {
+        cells
+            .iter()
+            .map(|cell| format!("<{}>{}</{}>", tag, escape_html(cell), tag))
+            .collect::<String>()
+    };
+    let headings = HEADINGS
+        .iter()
+        .map(|heading| heading.to_string())
+        .collect::<Vec<String>>();
+    let mut table = format!(
+        "<table border=\"1\" cellpadding=\"4\" cellspacing=\"0\">\n<tr>{}</tr>\n",
+        cells(&headings, "th")
+    );
+    for row in rows {
+        table += &format!("<tr>{}</tr>\n", cells(&row_cells(row), "td"));
+    }
+    table + "</table>"
+}
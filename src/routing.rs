@@ -0,0 +1,292 @@
+use crate::config::Severity;
+use crate::data::MonitorEvent;
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::NotifierId;
+use crate::schedule::ScheduleConfig;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A routing rule which sends matching events to extra notifiers
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RouteConfig {
+    #[serde(rename = "match", default)]
+    pub matchers: RouteMatchers,
+    pub notifiers: Vec<NotifierId>,
+    /// Send matching events to this route's notifiers instead of those configured for the
+    /// monitor on the fileset
+    #[serde(default)]
+    pub replace: bool,
+    /// Carry on evaluating later routes after this one matches
+    #[serde(rename = "continue", default)]
+    pub continue_matching: bool,
+}
+
+/// Conditions an event must meet for a route to apply. Unset matchers match anything.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RouteMatchers {
+    /// Severities of the monitor. Monitors without a severity don't match.
+    pub severity: Option<Vec<Severity>>,
+    pub filesets: Option<Vec<FileSetId>>,
+    pub monitors: Option<Vec<MonitorId>>,
+    /// Values which named captures in the monitor's regex must have
+    #[serde(default)]
+    pub captures: HashMap<String, String>,
+    /// Times of day and days of the week during which the route applies
+    pub schedule: Option<ScheduleConfig>,
+}
+
+/// Picks the notifiers for each event from the routing table
+pub(crate) struct Router {
+    routes: Vec<RouteConfig>,
+    monitor_severities: HashMap<MonitorId, Severity>,
+}
+
+impl Router {
+    pub(crate) fn new(
+        routes: Vec<RouteConfig>,
+        monitor_severities: HashMap<MonitorId, Severity>,
+    ) -> Router {
+        Router {
+            routes,
+            monitor_severities,
+        }
+    }
+
+    /// Check that all notifier IDs referenced by the routes exist, and that their schedules
+    /// are complete
+    pub(crate) fn validate<T>(&self, notifiers: &HashMap<NotifierId, T>) {
+        for notifier_id in self.routes.iter().flat_map(|route| &route.notifiers) {
+            if !notifiers.contains_key(notifier_id) {
+                panic!("Invalid notifier ID {:?} in routes", notifier_id);
+            }
+        }
+        for (index, route) in self.routes.iter().enumerate() {
+            if let Some(schedule) = &route.matchers.schedule {
+                schedule.validate(&format!("route {}", index + 1));
+            }
+        }
+    }
+
+    pub(crate) fn has_routes(&self) -> bool {
+        !self.routes.is_empty()
+    }
+
+    /// The notifiers for an event: those configured for the monitor on the fileset, followed by
+    /// those of any matching routes. If a matching route replaces the monitor's notifiers only
+    /// the routes' notifiers are used. None if there are no notifiers at all.
+    pub(crate) fn notifiers_for_event(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        ev: &MonitorEvent,
+        notifier_ids: &Option<Vec<NotifierId>>,
+    ) -> Option<Vec<NotifierId>> {
+        let mut routed: Vec<NotifierId> = vec![];
+        let mut replace = false;
+        for route in &self.routes {
+            if !self.matches(&route.matchers, fileset_id, monitor_id, ev) {
+                continue;
+            }
+            replace |= route.replace;
+            for notifier_id in &route.notifiers {
+                if !routed.contains(notifier_id) {
+                    routed.push(notifier_id.clone());
+                }
+            }
+            if !route.continue_matching {
+                break;
+            }
+        }
+        if replace {
+            return Some(routed);
+        }
+        let mut notifiers = notifier_ids.clone().unwrap_or_default();
+        for notifier_id in routed {
+            if !notifiers.contains(&notifier_id) {
+                notifiers.push(notifier_id);
+            }
+        }
+        if notifiers.is_empty() && notifier_ids.is_none() {
+            None
+        } else {
+            Some(notifiers)
+        }
+    }
+
+    fn matches(
+        &self,
+        matchers: &RouteMatchers,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        ev: &MonitorEvent,
+    ) -> bool {
+        let severity = self.monitor_severities.get(monitor_id);
+        matchers
+            .severity
+            .as_ref()
+            .is_none_or(|severities| severity.is_some_and(|s| severities.contains(s)))
+            && matchers
+                .filesets
+                .as_ref()
+                .is_none_or(|ids| ids.contains(fileset_id))
+            && matchers
+                .monitors
+                .as_ref()
+                .is_none_or(|ids| ids.contains(monitor_id))
+            && matchers
+                .captures
+                .iter()
+                .all(|(name, value)| ev.captures.get(name) == Some(value))
+            && matchers
+                .schedule
+                .as_ref()
+                .is_none_or(|schedule| schedule.is_active(Utc::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn router(routes: serde_json::Value) -> Router {
+        Router::new(
+            serde_json::from_value(routes).unwrap(),
+            HashMap::from([("errors".to_string(), Severity::Critical)]),
+        )
+    }
+
+    fn event(captures: serde_json::Value) -> MonitorEvent {
+        serde_json::from_value(json!({
+            "lines": [],
+            "captures": captures,
+            "awaiting_lines": 0,
+            "awaiting_lines_from": "",
+            "notify_by": Utc::now(),
+        }))
+        .unwrap()
+    }
+
+    fn notifiers(
+        router: &Router,
+        fileset_id: &str,
+        monitor_id: &str,
+        ev: &MonitorEvent,
+        notifier_ids: Option<&[&str]>,
+    ) -> Option<Vec<NotifierId>> {
+        router.notifiers_for_event(
+            &fileset_id.to_string(),
+            &monitor_id.to_string(),
+            ev,
+            &notifier_ids.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+        )
+    }
+
+    fn ids(ids: &[&str]) -> Option<Vec<NotifierId>> {
+        Some(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn adds_notifiers_of_first_matching_route() {
+        let router = router(json!([
+            {"match": {"severity": ["warning"]}, "notifiers": ["warnings"]},
+            {"match": {"severity": ["critical"], "filesets": ["web"]}, "notifiers": ["pager"]},
+            {"match": {"monitors": ["errors"]}, "notifiers": ["chat"]},
+        ]));
+        let ev = event(json!({}));
+        assert_eq!(
+            notifiers(&router, "web", "errors", &ev, Some(&["email"])),
+            ids(&["email", "pager"])
+        );
+        assert_eq!(
+            notifiers(&router, "db", "errors", &ev, None),
+            ids(&["chat"])
+        );
+        // Monitors without a severity don't match severity conditions
+        assert_eq!(notifiers(&router, "web", "other", &ev, None), None);
+    }
+
+    #[test]
+    fn matches_captures() {
+        let router = router(json!([
+            {"match": {"captures": {"tenant": "enterprise"}}, "notifiers": ["enterprise"]},
+        ]));
+        assert_eq!(
+            notifiers(
+                &router,
+                "web",
+                "errors",
+                &event(json!({"tenant": "enterprise"})),
+                None
+            ),
+            ids(&["enterprise"])
+        );
+        assert_eq!(
+            notifiers(
+                &router,
+                "web",
+                "errors",
+                &event(json!({"tenant": "free"})),
+                None
+            ),
+            None
+        );
+        assert_eq!(
+            notifiers(&router, "web", "errors", &event(json!({})), Some(&[])),
+            ids(&[])
+        );
+    }
+
+    #[test]
+    fn continues_to_later_routes_only_when_asked() {
+        let router = router(json!([
+            {"match": {"filesets": ["web"]}, "notifiers": ["chat"], "continue": true},
+            {"match": {"monitors": ["errors"]}, "notifiers": ["pager", "chat"]},
+            {"match": {}, "notifiers": ["email"]},
+        ]));
+        let ev = event(json!({}));
+        assert_eq!(
+            notifiers(&router, "web", "errors", &ev, Some(&["pager"])),
+            ids(&["pager", "chat"])
+        );
+        assert_eq!(
+            notifiers(&router, "web", "other", &ev, None),
+            ids(&["chat", "email"])
+        );
+    }
+
+    #[test]
+    fn replaces_monitor_notifiers_when_asked() {
+        let router = router(json!([
+            {"match": {"captures": {"tenant": "test"}}, "notifiers": [], "replace": true},
+            {"match": {"filesets": ["web"]}, "notifiers": ["pager"], "replace": true, "continue": true},
+            {"match": {"monitors": ["errors"]}, "notifiers": ["chat"]},
+        ]));
+        assert_eq!(
+            notifiers(
+                &router,
+                "web",
+                "errors",
+                &event(json!({})),
+                Some(&["email"])
+            ),
+            ids(&["pager", "chat"])
+        );
+        assert_eq!(
+            notifiers(&router, "db", "errors", &event(json!({})), Some(&["email"])),
+            ids(&["email", "chat"])
+        );
+        assert_eq!(
+            notifiers(
+                &router,
+                "web",
+                "errors",
+                &event(json!({"tenant": "test"})),
+                Some(&["email"])
+            ),
+            ids(&[])
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// A recurring period of time, defined either as weekday/time-of-day ranges,
+/// as a cron expression plus a duration, or both. Times are interpreted in `timezone`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleConfig {
+    /// Timezone in which the ranges and cron expression are evaluated. Defaults to UTC.
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Weekday/time-of-day ranges during which the schedule is active
+    #[serde(default)]
+    pub ranges: Vec<TimeRangeConfig>,
+    /// Cron expression (sec min hour day-of-month month day-of-week [year]) marking
+    /// the start of each active period
+    pub cron: Option<cron::Schedule>,
+    /// Length in seconds of each active period started by `cron`
+    pub duration: Option<usize>,
+}
+
+/// A time-of-day range on some days of the week. If `end` is earlier than `start`
+/// the range runs past midnight into the following day.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TimeRangeConfig {
+    /// Days on which the range starts. Empty means every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+impl ScheduleConfig {
+    /// Check that a cron expression comes with a duration, as without one the schedule would
+    /// never be active
+    pub(crate) fn validate(&self, name: &str) {
+        if self.cron.is_some() && self.duration.is_none() {
+            panic!(
+                "Schedule for {} has a cron expression but no duration",
+                name
+            );
+        }
+    }
+
+    /// Whether the schedule is active at the given moment
+    pub(crate) fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        let time = local.time();
+        let today = local.weekday();
+        let in_range = self.ranges.iter().any(|range| {
+            if range.start <= range.end {
+                range.applies_on(today) && time >= range.start && time < range.end
+            } else {
+                (range.applies_on(today) && time >= range.start)
+                    || (range.applies_on(today.pred()) && time < range.end)
+            }
+        });
+        if in_range {
+            return true;
+        }
+        if let (Some(cron), Some(duration)) = (&self.cron, self.duration) {
+            // Active if the cron expression fired within the last `duration` seconds
+            let window_start = i64::try_from(duration)
+                .ok()
+                .and_then(Duration::try_seconds)
+                .and_then(|duration| local.checked_sub_signed(duration));
+            if let Some(started) = window_start.and_then(|start| cron.after(&start).next()) {
+                return started <= local;
+            }
+        }
+        false
+    }
+}
+
+impl TimeRangeConfig {
+    fn applies_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+}
@@ -0,0 +1,382 @@
+use crate::fileset::FileSetId;
+use crate::monitor::MonitorId;
+use crate::notifier::NotifierId;
+use crate::schedule::ScheduleConfig;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Newtype
+pub(crate) type MaintenanceWindowId = String;
+
+/// Newtype
+pub(crate) type SilenceId = String;
+
+/// Scheduled maintenance windows and the filesets, monitors and notifiers they're attached to
+#[derive(Default)]
+pub(crate) struct MaintenanceWindows {
+    windows: HashMap<MaintenanceWindowId, ScheduleConfig>,
+    filesets: HashMap<FileSetId, Vec<MaintenanceWindowId>>,
+    monitors: HashMap<MonitorId, Vec<MaintenanceWindowId>>,
+    notifiers: HashMap<NotifierId, Vec<MaintenanceWindowId>>,
+}
+
+impl MaintenanceWindows {
+    /// Create from the maintenance window definitions in the config
+    pub(crate) fn new(windows: HashMap<MaintenanceWindowId, ScheduleConfig>) -> MaintenanceWindows {
+        for (window_id, schedule) in &windows {
+            schedule.validate(&format!("maintenance window {:?}", window_id));
+        }
+        MaintenanceWindows {
+            windows,
+            ..Default::default()
+        }
+    }
+
+    /// Attach a list of windows to a fileset
+    pub(crate) fn attach_to_fileset(
+        &mut self,
+        fileset_id: &FileSetId,
+        window_ids: &Option<Vec<MaintenanceWindowId>>,
+    ) {
+        if let Some(window_ids) = self.validate(fileset_id, window_ids) {
+            self.filesets.insert(fileset_id.clone(), window_ids);
+        }
+    }
+
+    /// Attach a list of windows to a monitor
+    pub(crate) fn attach_to_monitor(
+        &mut self,
+        monitor_id: &MonitorId,
+        window_ids: &Option<Vec<MaintenanceWindowId>>,
+    ) {
+        if let Some(window_ids) = self.validate(monitor_id, window_ids) {
+            self.monitors.insert(monitor_id.clone(), window_ids);
+        }
+    }
+
+    /// Attach a list of windows to a notifier
+    pub(crate) fn attach_to_notifier(
+        &mut self,
+        notifier_id: &NotifierId,
+        window_ids: &Option<Vec<MaintenanceWindowId>>,
+    ) {
+        if let Some(window_ids) = self.validate(notifier_id, window_ids) {
+            self.notifiers.insert(notifier_id.clone(), window_ids);
+        }
+    }
+
+    /// Check that all window IDs referenced by the config for `id` exist
+    fn validate(
+        &self,
+        id: &str,
+        window_ids: &Option<Vec<MaintenanceWindowId>>,
+    ) -> Option<Vec<MaintenanceWindowId>> {
+        let window_ids = window_ids.as_ref()?;
+        for window_id in window_ids {
+            if !self.windows.contains_key(window_id) {
+                panic!(
+                    "Invalid maintenance window ID {:?} in config for {:?}",
+                    window_id, id
+                );
+            }
+        }
+        Some(window_ids.clone())
+    }
+
+    /// Find the first currently active window which applies to a fileset/monitor/notifier combination
+    pub(crate) fn active_window(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        notifier_id: &NotifierId,
+    ) -> Option<&MaintenanceWindowId> {
+        [
+            self.filesets.get(fileset_id),
+            self.monitors.get(monitor_id),
+            self.notifiers.get(notifier_id),
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|window_id| self.is_active(window_id))
+    }
+
+    /// Whether a given window is currently active
+    pub(crate) fn is_active(&self, window_id: &MaintenanceWindowId) -> bool {
+        self.windows
+            .get(window_id)
+            .map(|window| window.is_active(Utc::now()))
+            .unwrap_or(false)
+    }
+}
+
+/// Silences created at runtime via the HTTP API
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(transparent)]
+pub struct Silences {
+    silences: HashMap<SilenceId, Silence>,
+}
+
+/// A runtime silence. Notifications matching all of its matchers aren't sent until it expires.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Silence {
+    pub id: SilenceId,
+    pub matchers: SilenceMatchers,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Conditions a notification must meet to be silenced. Unset matchers match anything.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SilenceMatchers {
+    pub fileset: Option<FileSetId>,
+    pub monitor: Option<MonitorId>,
+    pub notifier: Option<NotifierId>,
+    /// Values which named captures in the monitor's regex must have
+    #[serde(default)]
+    pub captures: HashMap<String, String>,
+}
+
+/// Request body for creating a silence. Either `expires_at` or `duration` (in seconds) is required.
+#[derive(Deserialize)]
+pub struct NewSilence {
+    pub matchers: SilenceMatchers,
+    pub reason: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub duration: Option<usize>,
+}
+
+/// The configured filesets, monitors and notifiers, which silences can refer to
+#[derive(Default)]
+pub(crate) struct SilenceTargets {
+    pub(crate) monitors: HashMap<FileSetId, HashSet<MonitorId>>,
+    pub(crate) notifiers: HashSet<NotifierId>,
+}
+
+impl SilenceTargets {
+    /// Check that the IDs in a silence's matchers are configured
+    fn validate(&self, matchers: &SilenceMatchers) -> Result<(), String> {
+        if let Some(fileset_id) = &matchers.fileset {
+            if !self.monitors.contains_key(fileset_id) {
+                return Err(format!("unknown fileset {:?}", fileset_id));
+            }
+        }
+        if let Some(monitor_id) = &matchers.monitor {
+            let known = self
+                .monitors
+                .iter()
+                .filter(|(fileset_id, _)| {
+                    matchers.fileset.as_ref().is_none_or(|id| id == *fileset_id)
+                })
+                .any(|(_, monitors)| monitors.contains(monitor_id));
+            if !known {
+                return Err(format!("unknown monitor {:?}", monitor_id));
+            }
+        }
+        if let Some(notifier_id) = &matchers.notifier {
+            if !self.notifiers.contains(notifier_id) {
+                return Err(format!("unknown notifier {:?}", notifier_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Silences {
+    /// Create a silence from an API request
+    pub(crate) fn add(
+        &mut self,
+        new_silence: NewSilence,
+        targets: &SilenceTargets,
+    ) -> Result<Silence, String> {
+        if new_silence.matchers.is_empty() {
+            return Err("at least one matcher is required".to_string());
+        }
+        targets.validate(&new_silence.matchers)?;
+        let now = Utc::now();
+        let expires_at = match (new_silence.expires_at, new_silence.duration) {
+            (Some(expires_at), None) => expires_at,
+            (None, Some(duration)) => i64::try_from(duration)
+                .ok()
+                .and_then(Duration::try_seconds)
+                .and_then(|duration| now.checked_add_signed(duration))
+                .ok_or_else(|| "duration is too long".to_string())?,
+            _ => return Err("exactly one of expires_at or duration is required".to_string()),
+        };
+        if expires_at <= now {
+            return Err("expiry must be in the future".to_string());
+        }
+        let silence = Silence {
+            id: Uuid::new_v4().to_string(),
+            matchers: new_silence.matchers,
+            reason: new_silence.reason,
+            created_at: now,
+            expires_at,
+        };
+        self.silences.insert(silence.id.clone(), silence.clone());
+        Ok(silence)
+    }
+
+    /// Remove a silence, returning it if it existed
+    pub(crate) fn remove(&mut self, silence_id: &SilenceId) -> Option<Silence> {
+        self.silences.remove(silence_id)
+    }
+
+    /// All unexpired silences, soonest expiry first
+    pub(crate) fn active(&self) -> Vec<&Silence> {
+        let now = Utc::now();
+        let mut active = self
+            .silences
+            .values()
+            .filter(|silence| silence.expires_at > now)
+            .collect::<Vec<&Silence>>();
+        active.sort_by_key(|silence| silence.expires_at);
+        active
+    }
+
+    /// Drop expired silences
+    pub(crate) fn remove_expired(&mut self) {
+        let now = Utc::now();
+        self.silences.retain(|_, silence| silence.expires_at > now);
+    }
+
+    /// Find an unexpired silence matching an event notification
+    pub(crate) fn matching_event(
+        &self,
+        fileset_id: &FileSetId,
+        monitor_id: &MonitorId,
+        notifier_id: &NotifierId,
+        captures: &HashMap<String, String>,
+    ) -> Option<&Silence> {
+        self.active().into_iter().find(|silence| {
+            let matchers = &silence.matchers;
+            matchers.fileset.as_ref().is_none_or(|id| id == fileset_id)
+                && matchers.monitor.as_ref().is_none_or(|id| id == monitor_id)
+                && matchers
+                    .notifier
+                    .as_ref()
+                    .is_none_or(|id| id == notifier_id)
+                && matchers
+                    .captures
+                    .iter()
+                    .all(|(name, value)| captures.get(name) == Some(value))
+        })
+    }
+
+    /// Find an unexpired silence matching a plain message notification. Only silences which
+    /// match on the notifier alone apply to messages.
+    pub(crate) fn matching_message(&self, notifier_id: &NotifierId) -> Option<&Silence> {
+        self.active().into_iter().find(|silence| {
+            let matchers = &silence.matchers;
+            matchers.fileset.is_none()
+                && matchers.monitor.is_none()
+                && matchers.captures.is_empty()
+                && matchers.notifier.as_ref() == Some(notifier_id)
+        })
+    }
+}
+
+impl SilenceMatchers {
+    fn is_empty(&self) -> bool {
+        self.fileset.is_none()
+            && self.monitor.is_none()
+            && self.notifier.is_none()
+            && self.captures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> SilenceTargets {
+        SilenceTargets {
+            monitors: HashMap::from([
+                ("web".to_string(), HashSet::from(["errors".to_string()])),
+                ("db".to_string(), HashSet::from(["slow".to_string()])),
+            ]),
+            notifiers: HashSet::from(["slack".to_string()]),
+        }
+    }
+
+    fn new_silence(matchers: SilenceMatchers, duration: usize) -> NewSilence {
+        NewSilence {
+            matchers,
+            reason: "testing".to_string(),
+            expires_at: None,
+            duration: Some(duration),
+        }
+    }
+
+    #[test]
+    fn add_accepts_configured_ids() {
+        let matchers = SilenceMatchers {
+            fileset: Some("web".to_string()),
+            monitor: Some("errors".to_string()),
+            notifier: Some("slack".to_string()),
+            ..Default::default()
+        };
+        let silence = Silences::default()
+            .add(new_silence(matchers, 60), &targets())
+            .unwrap();
+        assert!(silence.expires_at > silence.created_at);
+    }
+
+    #[test]
+    fn add_rejects_unknown_ids() {
+        let mut silences = Silences::default();
+        for matchers in [
+            SilenceMatchers {
+                fileset: Some("nope".to_string()),
+                ..Default::default()
+            },
+            SilenceMatchers {
+                monitor: Some("nope".to_string()),
+                ..Default::default()
+            },
+            // Configured, but on another fileset
+            SilenceMatchers {
+                fileset: Some("web".to_string()),
+                monitor: Some("slow".to_string()),
+                ..Default::default()
+            },
+            SilenceMatchers {
+                notifier: Some("nope".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(silences.add(new_silence(matchers, 60), &targets()).is_err());
+        }
+        assert!(silences.active().is_empty());
+    }
+
+    #[test]
+    fn add_rejects_overflowing_duration() {
+        let matchers = SilenceMatchers {
+            notifier: Some("slack".to_string()),
+            ..Default::default()
+        };
+        let result = Silences::default().add(new_silence(matchers, usize::MAX), &targets());
+        assert_eq!(result.unwrap_err(), "duration is too long");
+    }
+
+    #[test]
+    fn add_requires_a_matcher() {
+        let result =
+            Silences::default().add(new_silence(SilenceMatchers::default(), 60), &targets());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "cron expression but no duration")]
+    fn maintenance_window_needs_a_duration_with_cron() {
+        let schedule: ScheduleConfig = serde_json::from_value(serde_json::json!({
+            "cron": "0 0 2 * * *"
+        }))
+        .unwrap();
+        MaintenanceWindows::new(HashMap::from([("nightly".to_string(), schedule)]));
+    }
+}
@@ -0,0 +1,41 @@
+use minijinja::Environment;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A notification template using Jinja syntax, checked for errors when the config is loaded
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+}
+
+impl Template {
+    /// Parse a template, returning an error describing any syntax problems
+    pub(crate) fn new(source: String) -> Result<Template, minijinja::Error> {
+        Environment::new().template_from_str(&source)?;
+        Ok(Template { source })
+    }
+
+    /// Render the template with the given context
+    pub(crate) fn render<S: Serialize>(&self, context: S) -> Result<String, minijinja::Error> {
+        let env = Environment::new();
+        env.render_str(&self.source, context)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+        Template::new(source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}